@@ -0,0 +1,137 @@
+//! Per-customer deposit addresses.
+//!
+//! Deposit addresses are derived from the public key of a master account with
+//! the soft derivation path `/exchange/deposit/<n>`, so the shim never needs
+//! the master secret. The account for an address can be recovered from the
+//! master suri by appending the recorded path.
+use crate::{
+    db::{
+        Db,
+        Tree,
+    },
+    Error,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use serde::Serialize;
+use std::sync::Mutex;
+use substrate_primitives::crypto::{
+    Derive,
+    DeriveJunction,
+    Pair,
+    Ss58Codec,
+};
+
+/// A deposit address assigned to a customer.
+#[derive(Clone, Debug, Encode, Decode, Serialize)]
+pub struct DepositAddress {
+    /// Customer the address belongs to.
+    pub customer_id: String,
+    /// Ss58 encoded address.
+    pub address: String,
+    /// Derivation path relative to the master account.
+    pub path: String,
+}
+
+/// Registry of derived deposit addresses.
+pub struct DepositAddresses<P: Pair> {
+    master: Option<P::Public>,
+    db: Db,
+    /// Addresses by derivation index.
+    addresses: Tree<DepositAddress>,
+    /// Derivation index by ss58 address.
+    lookup: Tree<u64>,
+    lock: Mutex<()>,
+}
+
+impl<P: Pair> DepositAddresses<P> {
+    /// Opens the deposit address registry stored in `db`.
+    pub fn new(db: &Db, master: Option<P::Public>) -> Result<Self, Error> {
+        Ok(Self {
+            master,
+            db: db.clone(),
+            addresses: db.tree("deposit_addresses")?,
+            lookup: db.tree("deposit_lookup")?,
+            lock: Mutex::new(()),
+        })
+    }
+}
+
+impl<P: Pair> DepositAddresses<P>
+where
+    P::Public: Derive + Ss58Codec,
+{
+    /// Derives a new deposit address for `customer_id`.
+    pub fn create(&self, customer_id: &str) -> Result<DepositAddress, Error> {
+        let master = self.master.as_ref().ok_or(Error::NoDepositMaster)?;
+        let _lock = self.lock.lock().unwrap();
+        let index = self.db.generate_id()?;
+        let junctions = vec![
+            DeriveJunction::soft("exchange"),
+            DeriveJunction::soft("deposit"),
+            DeriveJunction::soft(index),
+        ];
+        let public = master
+            .derive(junctions.into_iter())
+            .ok_or(Error::NoDepositMaster)?;
+        let deposit = DepositAddress {
+            customer_id: customer_id.to_string(),
+            address: public.to_ss58check(),
+            path: format!("/exchange/deposit/{}", index),
+        };
+        self.addresses.insert(index.to_be_bytes(), &deposit)?;
+        self.lookup.insert(deposit.address.as_bytes(), &index)?;
+        self.addresses.flush()?;
+        Ok(deposit)
+    }
+
+    /// Returns the deposit address record for `address`.
+    pub fn get(&self, address: &str) -> Result<Option<DepositAddress>, Error> {
+        match self.lookup.get(address.as_bytes())? {
+            Some(index) => self.addresses.get(index.to_be_bytes()),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns all deposit addresses assigned to `customer_id`.
+    pub fn customer(&self, customer_id: &str) -> Result<Vec<DepositAddress>, Error> {
+        let mut addresses = Vec::new();
+        for entry in self.addresses.iter() {
+            let (_, deposit) = entry?;
+            if deposit.customer_id == customer_id {
+                addresses.push(deposit);
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use substrate_primitives::sr25519;
+
+    #[test]
+    fn test_derive_matches_suri() {
+        let path = std::env::temp_dir().join(format!("addresses-{}", std::process::id()));
+        let db = Db::open(&path).unwrap();
+        let master = sr25519::Pair::from_string("//Alice", None).unwrap().public();
+        let addresses = DepositAddresses::<sr25519::Pair>::new(&db, Some(master)).unwrap();
+
+        let deposit = addresses.create("customer").unwrap();
+        let pair = sr25519::Pair::from_string(&format!("//Alice{}", deposit.path), None)
+            .unwrap();
+        assert_eq!(deposit.address, pair.public().to_ss58check());
+        assert_eq!(
+            addresses.get(&deposit.address).unwrap().unwrap().customer_id,
+            "customer"
+        );
+        assert_eq!(addresses.customer("customer").unwrap().len(), 1);
+
+        drop(addresses);
+        drop(db);
+        std::fs::remove_dir_all(&path).ok();
+    }
+}
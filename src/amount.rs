@@ -0,0 +1,210 @@
+//! Parsing and formatting of token amounts.
+//!
+//! Amounts are accepted in three forms:
+//!
+//! - an integer in base units, e.g. `"1250000000000"`,
+//! - a `0x` prefixed hex integer in base units, e.g. `"0xa"`,
+//! - a decimal in whole units, recognized by a decimal point or the token
+//!   symbol, e.g. `"1.25"` or `"1.25 DOT"`.
+//!
+//! Decimals are converted with exact fixed point arithmetic. Amounts with more
+//! fractional digits than the token has decimals, or which don't fit a `u128`,
+//! are rejected.
+use crate::Error;
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use serde::Serialize;
+use sr_primitives::traits::{
+    SaturatedConversion,
+    UniqueSaturatedFrom,
+    UniqueSaturatedInto,
+};
+
+/// An amount in base units and in whole units for display.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, Serialize)]
+pub struct Amount {
+    /// Amount in base units.
+    pub value: String,
+    /// Amount in whole units followed by the token symbol.
+    pub formatted: String,
+}
+
+/// Decimals and symbol of the chain's token.
+#[derive(Clone, Debug)]
+pub struct Denomination {
+    /// Number of decimals of one whole unit.
+    pub decimals: u8,
+    /// Token symbol.
+    pub symbol: String,
+}
+
+impl Default for Denomination {
+    fn default() -> Self {
+        Self {
+            decimals: 0,
+            symbol: String::new(),
+        }
+    }
+}
+
+impl Denomination {
+    /// Parses an amount into base units.
+    pub fn parse(&self, amount: &str) -> Result<u128, Error> {
+        let amount = amount.trim();
+        let whole_units = !self.symbol.is_empty() && amount.ends_with(&self.symbol[..]);
+        let amount = if whole_units {
+            amount[..amount.len() - self.symbol.len()].trim_end()
+        } else {
+            amount
+        };
+        if amount.starts_with("0x") || amount.starts_with("0X") {
+            if whole_units {
+                return Err(Error::InvalidBalance)
+            }
+            return parse_digits(&amount[2..], 16)
+        }
+        if !whole_units && !amount.contains('.') {
+            return parse_digits(amount, 10)
+        }
+        let mut parts = amount.splitn(2, '.');
+        let integer = parts.next().unwrap_or_default();
+        let fraction = parts.next().unwrap_or_default();
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(Error::InvalidBalance)
+        }
+        if !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return Err(Error::InvalidBalance)
+        }
+        let fraction = fraction.trim_end_matches('0');
+        let decimals = self.decimals as usize;
+        if fraction.len() > decimals {
+            return Err(Error::ExcessPrecision)
+        }
+        // Shift the decimal point by appending the missing fractional digits.
+        let padding = "0".repeat(decimals - fraction.len());
+        let digits = format!("{}{}{}", integer, fraction, padding);
+        let digits = digits.trim_start_matches('0');
+        if digits.is_empty() {
+            return Ok(0)
+        }
+        parse_digits(digits, 10)
+    }
+
+    /// Formats an amount in base units as whole units followed by the symbol.
+    pub fn format(&self, value: u128) -> String {
+        let decimals = self.decimals as usize;
+        let digits = format!("{:0>1$}", value, decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        let fraction = fraction.trim_end_matches('0');
+        let mut formatted = integer.to_string();
+        if !fraction.is_empty() {
+            formatted.push('.');
+            formatted.push_str(fraction);
+        }
+        if !self.symbol.is_empty() {
+            formatted.push(' ');
+            formatted.push_str(&self.symbol);
+        }
+        formatted
+    }
+
+    /// Parses an amount into a balance.
+    pub fn balance<B>(&self, amount: &str) -> Result<B, Error>
+    where
+        B: UniqueSaturatedFrom<u128> + UniqueSaturatedInto<u128> + Copy,
+    {
+        let value = self.parse(amount)?;
+        let balance: B = value.saturated_into();
+        if balance.saturated_into::<u128>() != value {
+            return Err(Error::AmountOverflow)
+        }
+        Ok(balance)
+    }
+
+    /// Returns `balance` in base and whole units.
+    pub fn amount<B: UniqueSaturatedInto<u128>>(&self, balance: B) -> Amount {
+        let value: u128 = balance.saturated_into();
+        Amount {
+            value: value.to_string(),
+            formatted: self.format(value),
+        }
+    }
+}
+
+fn parse_digits(digits: &str, radix: u32) -> Result<u128, Error> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(Error::InvalidBalance)
+    }
+    u128::from_str_radix(digits, radix).map_err(|_| Error::AmountOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dot() -> Denomination {
+        Denomination {
+            decimals: 12,
+            symbol: "DOT".into(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let dot = dot();
+        assert_eq!(dot.parse("1250000000000").unwrap(), 1_250_000_000_000);
+        assert_eq!(dot.parse("0xa").unwrap(), 10);
+        assert_eq!(dot.parse("1.25").unwrap(), 1_250_000_000_000);
+        assert_eq!(dot.parse("1.25 DOT").unwrap(), 1_250_000_000_000);
+        assert_eq!(dot.parse("2 DOT").unwrap(), 2_000_000_000_000);
+        assert_eq!(dot.parse(".5").unwrap(), 500_000_000_000);
+        assert_eq!(dot.parse("0.000000000001").unwrap(), 1);
+        assert_eq!(dot.parse("1.0000000000010").unwrap(), 1_000_000_000_001);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let dot = dot();
+        match dot.parse("0.0000000000001") {
+            Err(Error::ExcessPrecision) => {}
+            other => panic!("expected excess precision, got {:?}", other),
+        }
+        match dot.parse("340282366920938463463374607431768211456") {
+            Err(Error::AmountOverflow) => {}
+            other => panic!("expected overflow, got {:?}", other),
+        }
+        match dot.parse("340282366920938463463374607431.768211455") {
+            Err(Error::AmountOverflow) => {}
+            other => panic!("expected overflow, got {:?}", other),
+        }
+        for invalid in &["", ".", "-1", "+1", "1e3", "0x", "0xg", "1.2.3", "0x1 DOT"] {
+            match dot.parse(invalid) {
+                Err(Error::InvalidBalance) => {}
+                other => panic!("expected invalid balance for {:?}: {:?}", invalid, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let dot = dot();
+        assert_eq!(dot.format(1_250_000_000_000), "1.25 DOT");
+        assert_eq!(dot.format(2_000_000_000_000), "2 DOT");
+        assert_eq!(dot.format(1), "0.000000000001 DOT");
+        assert_eq!(dot.format(0), "0 DOT");
+        assert_eq!(Denomination::default().format(42), "42");
+        assert_eq!(dot.parse(&dot.format(123_456_789)).unwrap(), 123_456_789);
+    }
+
+    #[test]
+    fn test_balance_overflow() {
+        let dot = dot();
+        assert_eq!(dot.balance::<u64>("1.5").unwrap(), 1_500_000_000_000);
+        match dot.balance::<u64>("18446744.073709551616") {
+            Err(Error::AmountOverflow) => {}
+            other => panic!("expected overflow, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Balance breakdown of accounts into free, locked and transferable amounts.
+use crate::{
+    amount::{
+        Amount,
+        Denomination,
+    },
+    node::Node,
+    storage,
+    Error,
+    Exchange,
+};
+use futures::future::Future;
+use parity_scale_codec::Decode;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sr_primitives::traits::{
+    SaturatedConversion,
+    Saturating,
+};
+use substrate_primitives::storage::StorageKey;
+use substrate_subxt::{
+    srml::{
+        balances::Balances,
+        system::System,
+    },
+    Client,
+};
+
+type Balance<T> = <T as Balances>::Balance;
+
+/// `WithdrawReason::TransactionPayment`.
+const REASON_TRANSACTION_PAYMENT: i8 = 0b0000_0001;
+/// `WithdrawReason::Transfer`.
+const REASON_TRANSFER: i8 = 0b0000_0010;
+/// `WithdrawReason::Reserve`.
+const REASON_RESERVE: i8 = 0b0000_0100;
+/// `WithdrawReason::Fee`.
+const REASON_FEE: i8 = 0b0000_1000;
+
+/// A balance lock as stored by the balances module.
+#[derive(Decode)]
+struct BalanceLock<Balance, BlockNumber> {
+    id: [u8; 8],
+    amount: Balance,
+    until: BlockNumber,
+    reasons: i8,
+}
+
+/// A vesting schedule as stored by the balances module.
+#[derive(Decode)]
+struct VestingSchedule<Balance> {
+    offset: Balance,
+    per_block: Balance,
+}
+
+/// Block to query the state at.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum At {
+    /// Canonical block with the given number.
+    Number(u64),
+    /// Block with the given hex encoded hash.
+    Hash(String),
+}
+
+/// A balance lock on an account.
+#[derive(Clone, Debug, Serialize)]
+pub struct Lock {
+    /// Identifier of the lock, usually the ascii name of the locking module.
+    pub id: String,
+    /// Locked amount.
+    pub amount: Amount,
+    /// Block the lock expires at.
+    pub until: u64,
+    /// Operations the lock restricts.
+    pub reasons: Vec<&'static str>,
+}
+
+/// Balance breakdown of an account.
+#[derive(Clone, Debug, Serialize)]
+pub struct AccountBalance {
+    /// Hash of the block the balance was queried at.
+    pub block_hash: String,
+    /// Number of the block the balance was queried at.
+    pub block_number: u64,
+    /// Free balance.
+    pub free: Amount,
+    /// Reserved balance.
+    pub reserved: Amount,
+    /// Sum of the free and reserved balance.
+    pub total: Amount,
+    /// Balance still locked by the vesting schedule.
+    pub vesting: Amount,
+    /// Locks active at the queried block.
+    pub locks: Vec<Lock>,
+    /// Free balance a transfer can move.
+    pub transferable: Amount,
+}
+
+/// Balance of one account of a batch query.
+#[derive(Clone, Debug, Serialize)]
+pub struct BatchEntry {
+    /// Queried address.
+    pub address: String,
+    /// Balance of the account, `None` if the query failed.
+    pub balance: Option<AccountBalance>,
+    /// Reason the query failed.
+    pub error: Option<String>,
+}
+
+/// Balances of a batch of accounts queried at one block.
+#[derive(Clone, Debug, Serialize)]
+pub struct AccountsBalances {
+    /// Hash of the block the balances were queried at.
+    pub block_hash: String,
+    /// Number of the block the balances were queried at.
+    pub block_number: u64,
+    /// Balances in the order of the queried addresses.
+    pub balances: Vec<BatchEntry>,
+}
+
+fn reasons(reasons: i8) -> Vec<&'static str> {
+    [
+        (REASON_TRANSACTION_PAYMENT, "transaction_payment"),
+        (REASON_TRANSFER, "transfer"),
+        (REASON_RESERVE, "reserve"),
+        (REASON_FEE, "fee"),
+    ]
+    .iter()
+    .filter(|(reason, _)| reasons & reason != 0)
+    .map(|(_, name)| *name)
+    .collect()
+}
+
+fn lock_id(id: &[u8; 8]) -> String {
+    if id.iter().all(|byte| byte.is_ascii_graphic() || *byte == b' ') {
+        String::from_utf8_lossy(id).trim_end().to_string()
+    } else {
+        format!("0x{}", hex::encode(id))
+    }
+}
+
+/// Balance breakdown of an account in base units.
+struct Breakdown<T: Exchange> {
+    free: Balance<T>,
+    reserved: Balance<T>,
+    vesting: Balance<T>,
+    locks: Vec<BalanceLock<Balance<T>, T::BlockNumber>>,
+}
+
+impl<T: Exchange> Breakdown<T> {
+    /// Returns the breakdown of the stored balances, locks and vesting
+    /// schedule of an account at the block `block_number`.
+    fn new(
+        free: Option<Balance<T>>,
+        reserved: Option<Balance<T>>,
+        locks: Option<Vec<BalanceLock<Balance<T>, T::BlockNumber>>>,
+        vesting: Option<VestingSchedule<Balance<T>>>,
+        block_number: T::BlockNumber,
+    ) -> Self {
+        let number: u64 = block_number.saturated_into();
+        let vesting = vesting
+            .map(|schedule| {
+                let vested = schedule.per_block.saturating_mul(number.saturated_into());
+                schedule.offset.saturating_sub(vested)
+            })
+            .unwrap_or_default();
+        let locks = locks
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|lock| lock.until > block_number)
+            .collect();
+        Self {
+            free: free.unwrap_or_default(),
+            reserved: reserved.unwrap_or_default(),
+            vesting,
+            locks,
+        }
+    }
+
+    /// Returns the part of the free balance a transfer can move.
+    fn transferable(&self) -> Balance<T> {
+        let locked = self
+            .locks
+            .iter()
+            .filter(|lock| lock.reasons & REASON_TRANSFER != 0)
+            .map(|lock| lock.amount)
+            .fold(self.vesting, std::cmp::max);
+        self.free.saturating_sub(locked)
+    }
+}
+
+/// Returns the storage keys of the free balance, reserved balance, locks and
+/// vesting schedule of `account`.
+fn keys<T: Exchange>(account: &T::AccountId) -> [StorageKey; 4] {
+    [
+        storage::map_key(b"Balances FreeBalance", account),
+        storage::map_key(b"Balances ReservedBalance", account),
+        storage::map_key(b"Balances Locks", account),
+        storage::map_key(b"Balances Vesting", account),
+    ]
+}
+
+fn breakdown<T: Exchange>(
+    client: &Client<T>,
+    account: &T::AccountId,
+    block_hash: T::Hash,
+    block_number: T::BlockNumber,
+) -> impl Future<Item = Breakdown<T>, Error = Error> {
+    let [free, reserved, locks, vesting] = keys::<T>(account);
+    let free = storage::fetch(client, free, block_hash);
+    let reserved = storage::fetch(client, reserved, block_hash);
+    let locks = storage::fetch(client, locks, block_hash);
+    let vesting = storage::fetch(client, vesting, block_hash);
+    free.join4(reserved, locks, vesting)
+        .map(move |(free, reserved, locks, vesting)| {
+            Breakdown::new(free, reserved, locks, vesting, block_number)
+        })
+}
+
+/// Decodes the stored `value` of a batch query.
+fn decode<V: Decode>(value: Option<&Vec<u8>>) -> Result<Option<V>, Error> {
+    value
+        .map(|value| Decode::decode(&mut &value[..]).map_err(|_| Error::InvalidResponse))
+        .transpose()
+}
+
+/// Returns the balance of the account with the `breakdown` in the block
+/// `block_hash` with the number `block_number`.
+fn account_balance_of<T: Exchange>(
+    denomination: &Denomination,
+    breakdown: Breakdown<T>,
+    block_hash: T::Hash,
+    block_number: T::BlockNumber,
+) -> AccountBalance {
+    AccountBalance {
+        block_hash: format!("{:?}", block_hash),
+        block_number: block_number.saturated_into(),
+        free: denomination.amount(breakdown.free),
+        reserved: denomination.amount(breakdown.reserved),
+        total: denomination.amount(breakdown.free.saturating_add(breakdown.reserved)),
+        vesting: denomination.amount(breakdown.vesting),
+        locks: breakdown
+            .locks
+            .iter()
+            .map(|lock| {
+                Lock {
+                    id: lock_id(&lock.id),
+                    amount: denomination.amount(lock.amount),
+                    until: lock.until.saturated_into(),
+                    reasons: reasons(lock.reasons),
+                }
+            })
+            .collect(),
+        transferable: denomination.amount(breakdown.transferable()),
+    }
+}
+
+/// Fetches the balance breakdown of `account` in the block `block_hash`
+/// with the number `block_number`.
+pub fn account_balance<T: Exchange>(
+    client: &Client<T>,
+    denomination: &Denomination,
+    account: &T::AccountId,
+    block_hash: T::Hash,
+    block_number: T::BlockNumber,
+) -> impl Future<Item = AccountBalance, Error = Error>
+{
+    let denomination = denomination.clone();
+    breakdown(client, account, block_hash, block_number).map(move |breakdown| {
+        account_balance_of(&denomination, breakdown, block_hash, block_number)
+    })
+}
+
+/// Fetches the balance breakdowns of `accounts` in the block `block_hash`
+/// with the number `block_number` with a single storage query.
+///
+/// Returns the balances in the order of `accounts`.
+pub fn accounts_balances<T: Exchange>(
+    node: &Node<T>,
+    denomination: &Denomination,
+    accounts: &[T::AccountId],
+    block_hash: T::Hash,
+    block_number: T::BlockNumber,
+) -> impl Future<Item = Vec<Result<AccountBalance, Error>>, Error = Error> {
+    let keys: Vec<[StorageKey; 4]> = accounts.iter().map(keys::<T>).collect();
+    let flat: Vec<StorageKey> = keys.iter().flat_map(|keys| keys.iter().cloned()).collect();
+    let denomination = denomination.clone();
+    node.storage_at(&flat, block_hash).map(move |values| {
+        keys.iter()
+            .map(|[free, reserved, locks, vesting]| {
+                let breakdown = Breakdown::<T>::new(
+                    decode(values.get(&free.0))?,
+                    decode(values.get(&reserved.0))?,
+                    decode(values.get(&locks.0))?,
+                    decode(values.get(&vesting.0))?,
+                    block_number,
+                );
+                Ok(account_balance_of(&denomination, breakdown, block_hash, block_number))
+            })
+            .collect()
+    })
+}
+
+/// Fetches the free and the transferable balance of `account` in the block
+/// `block_hash` with the number `block_number`.
+pub fn transferable<T: Exchange>(
+    client: &Client<T>,
+    account: &T::AccountId,
+    block_hash: T::Hash,
+    block_number: T::BlockNumber,
+) -> impl Future<Item = (Balance<T>, Balance<T>), Error = Error> {
+    breakdown(client, account, block_hash, block_number)
+        .map(|breakdown| (breakdown.free, breakdown.transferable()))
+}
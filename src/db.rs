@@ -0,0 +1,221 @@
+//! Persistent state of the shim.
+//!
+//! Values are stored SCALE encoded in named trees of an embedded sled
+//! database. The layout version of the values of a tree is recorded in the
+//! `versions` tree, so a tree can be migrated when its layout changes. Writes
+//! to several trees which must not be separated by a crash are collected in a
+//! `Batch` and applied in one transaction.
+use crate::Error;
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use sled::{
+    TransactionError,
+    Transactional,
+};
+use std::{
+    marker::PhantomData,
+    path::Path,
+};
+
+/// Embedded database.
+#[derive(Clone)]
+pub struct Db {
+    db: sled::Db,
+}
+
+impl Db {
+    /// Opens the database at `path`, creating it if it doesn't exist.
+    pub fn open<A: AsRef<Path>>(path: A) -> Result<Self, Error> {
+        Ok(Self {
+            db: sled::Db::open(path)?,
+        })
+    }
+
+    /// Opens the tree called `name`.
+    pub fn tree<V: Encode + Decode>(&self, name: &str) -> Result<Tree<V>, Error> {
+        Ok(Tree {
+            name: name.to_string(),
+            tree: self.db.open_tree(name)?,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns a unique monotonic id.
+    pub fn generate_id(&self) -> Result<u64, Error> {
+        Ok(self.db.generate_id()?)
+    }
+
+    fn versions(&self) -> Result<Tree<u8>, Error> {
+        self.tree("versions")
+    }
+
+    /// Returns the layout version of the tree `name`, `None` if it wasn't
+    /// recorded.
+    pub fn version(&self, name: &str) -> Result<Option<u8>, Error> {
+        self.versions()?.get(name)
+    }
+
+    /// Records the layout version of the tree `name` in `batch`.
+    pub fn set_version(&self, batch: &mut Batch, name: &str, version: u8) -> Result<(), Error> {
+        batch.insert(&self.versions()?, name, &version);
+        Ok(())
+    }
+
+    /// Applies the writes of `batch` atomically and flushes them to disk.
+    pub fn apply(&self, batch: Batch) -> Result<(), Error> {
+        let mut names: Vec<String> = Vec::new();
+        let mut trees: Vec<sled::Tree> = Vec::new();
+        let mut writes = Vec::with_capacity(batch.writes.len());
+        for (name, tree, key, value) in batch.writes {
+            let index = match names.iter().position(|other| *other == name) {
+                Some(index) => index,
+                None => {
+                    names.push(name);
+                    trees.push(tree);
+                    trees.len() - 1
+                }
+            };
+            writes.push((index, key, value));
+        }
+        if trees.is_empty() {
+            return Ok(())
+        }
+        let result = trees[..].transaction(|trees| {
+            for (index, key, value) in &writes {
+                match value {
+                    Some(value) => trees[*index].insert(key.clone(), value.clone())?,
+                    None => trees[*index].remove(key.clone())?,
+                };
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Storage(err)) => return Err(err.into()),
+            Err(err) => unreachable!("batches don't abort: {:?}", err),
+        }
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes to several trees applied atomically by `Db::apply`.
+#[derive(Default)]
+pub struct Batch {
+    writes: Vec<(String, sled::Tree, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl Batch {
+    /// Stores `value` under `key` in `tree`.
+    pub fn insert<K: AsRef<[u8]>, V: Encode>(&mut self, tree: &Tree<V>, key: K, value: &V) {
+        let value = Some(value.encode());
+        let write = (tree.name.clone(), tree.tree.clone(), key.as_ref().to_vec(), value);
+        self.writes.push(write);
+    }
+
+    /// Removes the value stored under `key` in `tree`.
+    pub fn remove<K: AsRef<[u8]>, V>(&mut self, tree: &Tree<V>, key: K) {
+        let write = (tree.name.clone(), tree.tree.clone(), key.as_ref().to_vec(), None);
+        self.writes.push(write);
+    }
+}
+
+/// A tree of SCALE encoded values of type `V`.
+pub struct Tree<V> {
+    name: String,
+    tree: sled::Tree,
+    _marker: PhantomData<V>,
+}
+
+impl<V> Clone for Tree<V> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            tree: self.tree.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+fn decode<V: Decode>(bytes: &[u8]) -> Result<V, Error> {
+    Decode::decode(&mut &bytes[..]).map_err(|_| Error::CorruptedDatabase)
+}
+
+impl<V: Encode + Decode> Tree<V> {
+    /// Returns the value stored under `key`.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<V>, Error> {
+        match self.tree.get(key)? {
+            Some(bytes) => Ok(Some(decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores `value` under `key`.
+    pub fn insert<K: AsRef<[u8]>>(&self, key: K, value: &V) -> Result<(), Error> {
+        self.tree.insert(key, value.encode())?;
+        Ok(())
+    }
+
+    /// Stores `value` under `key` if there is no value stored yet.
+    ///
+    /// Returns the existing value otherwise.
+    pub fn insert_new<K: AsRef<[u8]>>(&self, key: K, value: &V) -> Result<Option<V>, Error> {
+        match self.tree.cas(key, None as Option<&[u8]>, Some(value.encode()))? {
+            Ok(()) => Ok(None),
+            Err(Some(existing)) => Ok(Some(decode(&existing)?)),
+            Err(None) => Ok(None),
+        }
+    }
+
+    /// Removes the value stored under `key`.
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<V>, Error> {
+        match self.tree.remove(key)? {
+            Some(bytes) => Ok(Some(decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `true` if the tree has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns all entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, V), Error>> {
+        self.tree.iter().map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.to_vec(), decode(&value)?))
+        })
+    }
+
+    /// Returns all entries with a key starting with `prefix` in key order.
+    pub fn scan_prefix<K: AsRef<[u8]>>(
+        &self,
+        prefix: K,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, V), Error>> {
+        self.tree.scan_prefix(prefix).map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.to_vec(), decode(&value)?))
+        })
+    }
+
+    /// Returns all entries with a key greater or equal to `start` in key
+    /// order.
+    pub fn range_from<K: AsRef<[u8]>>(
+        &self,
+        start: K,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, V), Error>> {
+        self.tree.range(start.as_ref().to_vec()..).map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.to_vec(), decode(&value)?))
+        })
+    }
+
+    /// Flushes the tree to disk.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.tree.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Building and signing extrinsics.
+use crate::{
+    Error,
+    Exchange,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use sr_primitives::{
+    generic::{
+        Era,
+        UncheckedExtrinsic,
+    },
+    traits::{
+        Hash as _,
+        SignedExtension,
+        StaticLookup,
+    },
+    transaction_validity::TransactionValidityError,
+};
+use std::fmt::Debug;
+use substrate_primitives::{
+    blake2_256,
+    crypto::Pair,
+};
+use substrate_subxt::srml::system::System;
+
+/// Signed extrinsic of a runtime.
+pub type Extrinsic<T> = UncheckedExtrinsic<
+    <<T as System>::Lookup as StaticLookup>::Source,
+    <T as Exchange>::Call,
+    <<T as Exchange>::Pair as Pair>::Signature,
+    <T as System>::SignedExtra,
+>;
+
+/// Signed extension checking the genesis hash of the chain.
+///
+/// Encodes like the runtime's `CheckGenesis` but carries the genesis hash, so
+/// the additional signed data is known without access to the runtime storage.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CheckGenesis<H>(#[codec(skip)] pub H);
+
+impl<H> SignedExtension for CheckGenesis<H>
+where
+    H: Encode + Decode + Default + Clone + Debug + Eq + Send + Sync,
+{
+    type AccountId = ();
+    type Call = ();
+    type AdditionalSigned = H;
+    type Pre = ();
+
+    fn additional_signed(&self) -> Result<Self::AdditionalSigned, TransactionValidityError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Signed extension checking the era of a transaction.
+///
+/// Encodes like the runtime's `CheckEra` but carries the hash of the block
+/// the era starts at, or the genesis hash for immortal transactions.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CheckEra<H>(pub Era, #[codec(skip)] pub H);
+
+impl<H> SignedExtension for CheckEra<H>
+where
+    H: Encode + Decode + Default + Clone + Debug + Eq + Send + Sync,
+{
+    type AccountId = ();
+    type Call = ();
+    type AdditionalSigned = H;
+    type Pre = ();
+
+    fn additional_signed(&self) -> Result<Self::AdditionalSigned, TransactionValidityError> {
+        Ok(self.1.clone())
+    }
+}
+
+/// Returns the payload an extrinsic signer signs.
+///
+/// The payload is the SCALE encoded `(call, extra, additional_signed)` where
+/// `additional_signed` is the data the signed extensions add to the signature
+/// without including it in the extrinsic. Payloads longer than 256 bytes are
+/// hashed with blake2_256.
+pub fn signing_payload<T: Exchange>(
+    call: &T::Call,
+    extra: &T::SignedExtra,
+) -> Result<Vec<u8>, Error> {
+    let additional_signed = extra
+        .additional_signed()
+        .map_err(|_| Error::InvalidPayload)?;
+    let payload = (call, extra, additional_signed).encode();
+    if payload.len() > 256 {
+        Ok(blake2_256(&payload[..]).to_vec())
+    } else {
+        Ok(payload)
+    }
+}
+
+/// Builds the extrinsic for `call` signed with `signature`.
+pub fn signed<T: Exchange>(
+    call: T::Call,
+    signer: <T::Pair as Pair>::Public,
+    signature: <T::Pair as Pair>::Signature,
+    extra: T::SignedExtra,
+) -> Extrinsic<T>
+where
+    <T::Pair as Pair>::Public: Into<<T::Lookup as StaticLookup>::Source>,
+{
+    UncheckedExtrinsic::new_signed(call, signer.into(), signature, extra)
+}
+
+/// Signs `call` with `pair` for the era `era` starting at the block
+/// `era_hash`, paying `tip` to the block author.
+pub fn sign<T: Exchange>(
+    pair: &T::Pair,
+    call: T::Call,
+    nonce: T::Index,
+    era: Era,
+    tip: T::Balance,
+    genesis_hash: &T::Hash,
+    era_hash: &T::Hash,
+) -> Result<Extrinsic<T>, Error>
+where
+    <T::Pair as Pair>::Public: Into<<T::Lookup as StaticLookup>::Source>,
+{
+    let extra = T::signed_extra(nonce, era, tip, *genesis_hash, *era_hash);
+    let payload = signing_payload::<T>(&call, &extra)?;
+    let signature = pair.sign(&payload);
+    Ok(signed::<T>(call, pair.public(), signature, extra))
+}
+
+/// Returns the hash of an extrinsic.
+pub fn hash<T: Exchange, E: Encode>(extrinsic: &E) -> T::Hash {
+    T::Hashing::hash_of(extrinsic)
+}
+
+/// An already encoded extrinsic.
+pub struct Encoded(pub Vec<u8>);
+
+impl Encode for Encoded {
+    fn size_hint(&self) -> usize {
+        self.0.len()
+    }
+
+    fn using_encoded<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> R {
+        f(&self.0)
+    }
+}
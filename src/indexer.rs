@@ -0,0 +1,781 @@
+//! Detection of deposits to watched addresses and the credit feed of ready
+//! deposits.
+use crate::{
+    amount::{
+        Amount,
+        Denomination,
+    },
+    db::{
+        Batch,
+        Db,
+        Tree,
+    },
+    storage,
+    tracker::{
+        Head,
+        RETRY_DELAY,
+    },
+    webhook::{
+        Event,
+        Webhooks,
+    },
+    Error,
+    Exchange,
+};
+use futures::{
+    future::{
+        self,
+        Either,
+        Future,
+        Loop,
+    },
+    stream::{
+        self,
+        Stream,
+    },
+    sync::mpsc,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use serde::Serialize;
+use sr_primitives::traits::{
+    Header,
+    SaturatedConversion,
+};
+use srml_system::Phase;
+use std::{
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Instant,
+};
+use substrate_primitives::crypto::Ss58Codec;
+use substrate_subxt::{
+    srml::system::System,
+    Client,
+};
+use tokio::timer::Delay;
+
+/// A balance credit decoded from an event.
+pub struct Credit<T: Exchange> {
+    /// Account the balance comes from, `None` if it is newly issued or
+    /// paid out by a module.
+    pub from: Option<T::AccountId>,
+    /// Credited account.
+    pub to: T::AccountId,
+    /// Credited amount.
+    pub amount: T::Balance,
+}
+
+/// When deposits are ready to be credited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreditPolicy {
+    /// Once the block including the deposit is finalized.
+    Finality,
+    /// Once the block including the deposit is on the best chain with the
+    /// given number of confirmations, or is finalized.
+    Confirmations(u64),
+}
+
+impl Default for CreditPolicy {
+    fn default() -> Self {
+        CreditPolicy::Finality
+    }
+}
+
+/// An address the indexer records deposits for.
+#[derive(Clone, Debug, Encode, Decode, Serialize)]
+pub struct WatchedAddress {
+    /// Ss58 encoded address.
+    pub address: String,
+    /// Client supplied label, e.g. the customer the address belongs to.
+    pub label: Option<String>,
+}
+
+/// State of a deposit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepositState {
+    /// Included in a block which isn't finalized yet.
+    InBlock,
+    /// Included in a finalized block.
+    Finalized,
+    /// The block including the deposit was retracted.
+    Orphaned,
+}
+
+/// A deposit to a watched address.
+#[derive(Clone, Debug, Encode, Decode, Serialize)]
+pub struct Deposit {
+    /// Ss58 encoded address of the recipient.
+    pub address: String,
+    /// Label of the recipient.
+    pub label: Option<String>,
+    /// Ss58 encoded address of the sender, `None` for credits not coming
+    /// from an account.
+    pub from: Option<String>,
+    /// Deposited amount.
+    pub amount: Amount,
+    /// Hash of the block.
+    pub block_hash: String,
+    /// Number of the block.
+    pub block_number: u64,
+    /// Index of the extrinsic causing the deposit, `None` for deposits made
+    /// while finalizing the block.
+    pub extrinsic_index: Option<u32>,
+    /// Index of the event in the block.
+    pub event_index: u32,
+    /// State of the deposit.
+    pub state: DepositState,
+    /// Number of blocks from the block including the deposit up to the best
+    /// block, `0` if the deposit is orphaned.
+    pub confirmations: u64,
+    /// Position of the deposit in the credit feed, once it is ready to be
+    /// credited.
+    pub cursor: Option<u64>,
+}
+
+/// Last best block processed by the indexer.
+#[derive(Clone, Debug, Encode, Decode, Serialize)]
+pub struct Checkpoint {
+    /// Hash of the block.
+    pub hash: String,
+    /// Number of the block.
+    pub number: u64,
+}
+
+/// Result of replaying a range of blocks.
+#[derive(Clone, Debug, Serialize)]
+pub struct Backfill {
+    /// Number of the first replayed block.
+    pub from: u64,
+    /// Number of the last replayed block.
+    pub to: u64,
+    /// Deposits found which weren't recorded before.
+    pub deposits: Vec<Deposit>,
+}
+
+/// Deposits of the credit feed.
+#[derive(Clone, Debug, Serialize)]
+pub struct CreditReady {
+    /// Deposits which became ready to be credited, or were orphaned after
+    /// that, in feed order. Each deposit is in the state it was appended in.
+    pub deposits: Vec<Deposit>,
+    /// Cursor to pass to read the deposits following these.
+    pub cursor: u64,
+}
+
+/// Records deposits to watched addresses.
+pub struct Indexer<T: Exchange> {
+    client: Client<T>,
+    db: Db,
+    denomination: Denomination,
+    policy: CreditPolicy,
+    webhooks: Webhooks,
+    /// Receivers of new deposits.
+    listeners: Arc<Mutex<Vec<mpsc::UnboundedSender<Deposit>>>>,
+    best: Arc<Mutex<u64>>,
+    /// Held while recording the deposits of a block, so a block indexed and
+    /// backfilled at once records its deposits once.
+    recording: Arc<Mutex<()>>,
+    /// Watched addresses by account id.
+    watched: Tree<WatchedAddress>,
+    /// Deposits by account id, block number, block hash and event index.
+    deposits: Tree<Deposit>,
+    /// Keys of the deposits neither finalized nor orphaned by block number.
+    unsettled: Tree<Vec<u8>>,
+    /// Snapshots of the deposits in the credit feed by position.
+    feed: Tree<Deposit>,
+    /// Indexed blocks by number and hash.
+    indexed: Tree<()>,
+    /// The checkpoint.
+    checkpoint: Tree<Checkpoint>,
+}
+
+/// Key of the checkpoint.
+const CHECKPOINT: &[u8] = b"checkpoint";
+
+/// Number of missed blocks behind a new best block which are found by walking
+/// back its ancestors. Blocks missed before that are indexed by number.
+const CATCH_UP_DEPTH: u64 = 256;
+
+/// Name of the credit feed tree.
+const FEED: &str = "credit_feed";
+
+/// Layout version of the credit feed.
+const FEED_VERSION: u8 = 1;
+
+impl<T: Exchange> Clone for Indexer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            db: self.db.clone(),
+            denomination: self.denomination.clone(),
+            policy: self.policy,
+            webhooks: self.webhooks.clone(),
+            listeners: self.listeners.clone(),
+            best: self.best.clone(),
+            recording: self.recording.clone(),
+            watched: self.watched.clone(),
+            deposits: self.deposits.clone(),
+            unsettled: self.unsettled.clone(),
+            feed: self.feed.clone(),
+            indexed: self.indexed.clone(),
+            checkpoint: self.checkpoint.clone(),
+        }
+    }
+}
+
+impl<T: Exchange> Indexer<T>
+where
+    <T as System>::AccountId: Ss58Codec,
+{
+    /// Opens the watched addresses and deposits stored in `db`.
+    pub fn new(
+        client: Client<T>,
+        db: &Db,
+        denomination: Denomination,
+        policy: CreditPolicy,
+        webhooks: Webhooks,
+    ) -> Result<Self, Error> {
+        let deposits = db.tree("deposits")?;
+        let feed = db.tree(FEED)?;
+        match db.version(FEED)? {
+            Some(FEED_VERSION) => {}
+            None if feed.is_empty() => {
+                let mut batch = Batch::default();
+                db.set_version(&mut batch, FEED, FEED_VERSION)?;
+                db.apply(batch)?;
+            }
+            _ => return Err(Error::CorruptedDatabase),
+        }
+        Ok(Self {
+            client,
+            db: db.clone(),
+            denomination,
+            policy,
+            webhooks,
+            listeners: Default::default(),
+            best: Arc::new(Mutex::new(0)),
+            recording: Default::default(),
+            watched: db.tree("watched_addresses")?,
+            deposits,
+            unsettled: db.tree("unsettled_deposits")?,
+            feed,
+            indexed: db.tree("indexed_blocks")?,
+            checkpoint: db.tree("indexer_checkpoint")?,
+        })
+    }
+
+    /// Starts recording deposits to `account`.
+    pub fn watch(
+        &self,
+        account: &T::AccountId,
+        label: Option<String>,
+    ) -> Result<WatchedAddress, Error> {
+        let watched = WatchedAddress {
+            address: account.to_ss58check(),
+            label,
+        };
+        self.watched.insert(account.encode(), &watched)?;
+        self.watched.flush()?;
+        Ok(watched)
+    }
+
+    /// Stops recording deposits to `account`.
+    ///
+    /// Deposits recorded so far are kept.
+    pub fn unwatch(&self, account: &T::AccountId) -> Result<Option<WatchedAddress>, Error> {
+        let watched = self.watched.remove(account.encode())?;
+        self.watched.flush()?;
+        Ok(watched)
+    }
+
+    /// Returns the deposits to `account`, or to all accounts, in block order
+    /// per account.
+    pub fn deposits(&self, account: Option<&T::AccountId>) -> Result<Vec<Deposit>, Error> {
+        let prefix = account.map(Encode::encode).unwrap_or_default();
+        self.deposits
+            .scan_prefix(prefix)
+            .map(|entry| entry.map(|(_, deposit)| self.confirmed(deposit)))
+            .collect()
+    }
+
+    /// Returns the deposits of the credit feed following `cursor`, or all of
+    /// them.
+    pub fn credit_ready(&self, cursor: Option<u64>) -> Result<CreditReady, Error> {
+        let start = cursor.map(|cursor| cursor + 1).unwrap_or_default();
+        let mut ready = CreditReady {
+            deposits: Vec::new(),
+            cursor: cursor.unwrap_or_default(),
+        };
+        for entry in self.feed.range_from(start.to_be_bytes()) {
+            let (position, deposit) = entry?;
+            ready.deposits.push(self.confirmed(deposit));
+            ready.cursor = decode_number(&position);
+        }
+        Ok(ready)
+    }
+
+    /// Returns the last best block processed.
+    pub fn checkpoint(&self) -> Result<Option<Checkpoint>, Error> {
+        self.checkpoint.get(CHECKPOINT)
+    }
+
+    /// Sets the confirmations of `deposit` from the best block number.
+    fn confirmed(&self, mut deposit: Deposit) -> Deposit {
+        let best = *self.best.lock().unwrap();
+        deposit.confirmations = match deposit.state {
+            DepositState::Orphaned => 0,
+            _ if best < deposit.block_number => 0,
+            _ => best - deposit.block_number + 1,
+        };
+        deposit
+    }
+
+    /// Records the deposits to watched addresses in the block `block_hash`.
+    pub fn index_block(
+        &self,
+        block_hash: T::Hash,
+        block_number: T::BlockNumber,
+    ) -> impl Future<Item = Vec<Deposit>, Error = Error> {
+        let indexer = self.clone();
+        storage::events(&self.client, block_hash).and_then(move |events| {
+            let _recording = indexer.recording.lock().unwrap();
+            let mut deposits = Vec::new();
+            for (event_index, record) in events.iter().enumerate() {
+                let credit = match T::balance_credit(&record.event) {
+                    Some(credit) => credit,
+                    None => continue,
+                };
+                let watched = match indexer.watched.get(credit.to.encode())? {
+                    Some(watched) => watched,
+                    None => continue,
+                };
+                let deposit = Deposit {
+                    address: watched.address,
+                    label: watched.label,
+                    from: credit.from.map(|from| from.to_ss58check()),
+                    amount: indexer.denomination.amount(credit.amount),
+                    block_hash: format!("{:?}", block_hash),
+                    block_number: block_number.saturated_into(),
+                    extrinsic_index: match record.phase {
+                        Phase::ApplyExtrinsic(index) => Some(index),
+                        Phase::Finalization => None,
+                    },
+                    event_index: event_index as u32,
+                    state: DepositState::InBlock,
+                    confirmations: 0,
+                    cursor: None,
+                };
+                let mut key = credit.to.encode();
+                key.extend_from_slice(&deposit.block_number.to_be_bytes());
+                key.extend_from_slice(block_hash.as_ref());
+                key.extend_from_slice(&deposit.event_index.to_be_bytes());
+                if indexer.deposits.get(&key)?.is_some() {
+                    // The block was indexed before.
+                    continue
+                }
+                log::info!(
+                    "deposit of {} to {} in block {}",
+                    deposit.amount.formatted,
+                    deposit.address,
+                    deposit.block_number
+                );
+                let mut unsettled = deposit.block_number.to_be_bytes().to_vec();
+                unsettled.extend_from_slice(&key);
+                let mut batch = Batch::default();
+                batch.insert(&indexer.deposits, &key, &deposit);
+                batch.insert(&indexer.unsettled, unsettled, &key);
+                let event = Event::DepositDetected(deposit.clone());
+                indexer.webhooks.enqueue(&mut batch, event)?;
+                indexer.db.apply(batch)?;
+                indexer.publish(&deposit);
+                deposits.push(deposit);
+            }
+            Ok(deposits)
+        })
+    }
+
+    /// Returns a stream of the deposits found from now on.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<Deposit> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.listeners.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends a new `deposit` to the subscribers.
+    fn publish(&self, deposit: &Deposit) {
+        self.listeners
+            .lock()
+            .unwrap()
+            .retain(|listener| listener.unbounded_send(deposit.clone()).is_ok());
+    }
+
+    /// Appends a snapshot of `deposit` to the credit feed in `batch`.
+    fn append(&self, batch: &mut Batch, deposit: &mut Deposit) -> Result<(), Error> {
+        let position = self.db.generate_id()?;
+        if deposit.cursor.is_none() {
+            deposit.cursor = Some(position);
+        }
+        batch.insert(&self.feed, position.to_be_bytes(), deposit);
+        log::info!(
+            "deposit of {} to {} in block {} is {:?}",
+            deposit.amount.formatted,
+            deposit.address,
+            deposit.block_number,
+            deposit.state
+        );
+        Ok(())
+    }
+
+    /// Settles the unsettled deposits in blocks up to the number `number`.
+    ///
+    /// Deposits in canonical blocks are finalized if `finalized` is set, and
+    /// are otherwise credited as having enough confirmations. Deposits in
+    /// retracted blocks are orphaned.
+    fn settle(&self, number: u64, finalized: bool) -> impl Future<Item = (), Error = Error> {
+        let unsettled: Result<Vec<_>, Error> = self
+            .unsettled
+            .iter()
+            .take_while(|entry| {
+                match entry {
+                    Ok((position, _)) => decode_number(position) <= number,
+                    Err(_) => true,
+                }
+            })
+            .collect();
+        let unsettled = match unsettled {
+            Ok(unsettled) => unsettled,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        let indexer = self.clone();
+        let settle = stream::iter_ok(unsettled).for_each(move |(position, key)| {
+            let indexer = indexer.clone();
+            let deposit = match indexer.deposits.get(&key) {
+                Ok(Some(deposit)) => deposit,
+                Ok(None) => return Either::A(future::err(Error::CorruptedDatabase)),
+                Err(err) => return Either::A(future::err(err)),
+            };
+            if !finalized && deposit.cursor.is_some() {
+                return Either::A(future::ok(()))
+            }
+            let block_number: T::BlockNumber = deposit.block_number.saturated_into();
+            let settle = indexer
+                .client
+                .block_hash(Some(block_number.into()))
+                .map_err(Error::from)
+                .and_then(move |canonical| {
+                    let canonical = canonical
+                        .map(|hash| format!("{:?}", hash) == deposit.block_hash)
+                        .unwrap_or(false);
+                    indexer.settle_deposit(&position, &key, deposit, canonical, finalized)
+                });
+            Either::B(settle)
+        });
+        Either::B(settle)
+    }
+
+    fn settle_deposit(
+        &self,
+        position: &[u8],
+        key: &[u8],
+        mut deposit: Deposit,
+        canonical: bool,
+        finalized: bool,
+    ) -> Result<(), Error> {
+        let mut batch = Batch::default();
+        if !canonical {
+            deposit.state = DepositState::Orphaned;
+            if deposit.cursor.is_some() {
+                log::warn!("credited deposit in block {} orphaned", deposit.block_hash);
+                self.append(&mut batch, &mut deposit)?;
+            }
+            batch.remove(&self.unsettled, position);
+        } else if finalized {
+            deposit.state = DepositState::Finalized;
+            if deposit.cursor.is_none() {
+                self.append(&mut batch, &mut deposit)?;
+            }
+            batch.remove(&self.unsettled, position);
+        } else {
+            self.append(&mut batch, &mut deposit)?;
+        }
+        batch.insert(&self.deposits, key, &deposit);
+        match deposit.state {
+            DepositState::Orphaned => {
+                self.webhooks.enqueue(&mut batch, Event::DepositOrphaned(deposit))?
+            }
+            DepositState::Finalized => {
+                self.webhooks.enqueue(&mut batch, Event::DepositFinalized(deposit))?
+            }
+            DepositState::InBlock => {}
+        }
+        self.db.apply(batch)
+    }
+
+    /// Returns `true` if the block `hash` with the number `number` was
+    /// indexed, or is older than the indexed blocks.
+    fn is_indexed(&self, hash: &T::Hash, number: u64) -> Result<bool, Error> {
+        if self.indexed.get(block_key::<T>(number, hash))?.is_some() {
+            return Ok(true)
+        }
+        match self.indexed.iter().next() {
+            Some(entry) => {
+                let (first, _) = entry?;
+                Ok(number < decode_number(&first))
+            }
+            // Indexing starts at the first best block.
+            None => Ok(true),
+        }
+    }
+
+    /// Records that the block `hash` was indexed and checkpoints it.
+    fn indexed(&self, hash: T::Hash, number: T::BlockNumber) -> Result<(), Error> {
+        let number: u64 = number.saturated_into();
+        self.indexed.insert(block_key::<T>(number, &hash), &())?;
+        let checkpoint = Checkpoint {
+            hash: format!("{:?}", hash),
+            number,
+        };
+        self.checkpoint.insert(CHECKPOINT, &checkpoint)?;
+        self.checkpoint.flush()
+    }
+
+    /// Indexes the best block `header` and its ancestors which weren't
+    /// indexed yet, oldest first.
+    ///
+    /// If the checkpoint is more than `CATCH_UP_DEPTH` blocks behind, the
+    /// canonical blocks up to that depth are indexed by number first, so only
+    /// a bounded number of headers is held while walking back.
+    fn catch_up(&self, header: T::Header) -> impl Future<Item = (), Error = Error> {
+        let number: u64 = (*header.number()).saturated_into();
+        let checkpoint = match self.checkpoint() {
+            Ok(checkpoint) => checkpoint,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        let canonical = match checkpoint {
+            Some(checkpoint) if number > checkpoint.number + CATCH_UP_DEPTH => {
+                Either::A(self.index_canonical(checkpoint.number + 1, number - CATCH_UP_DEPTH))
+            }
+            _ => Either::B(future::ok(())),
+        };
+        let indexer = self.clone();
+        Either::B(canonical.and_then(move |_| indexer.walk_back(header)))
+    }
+
+    /// Indexes the canonical blocks from `from` to `to`, oldest first.
+    fn index_canonical(&self, from: u64, to: u64) -> impl Future<Item = (), Error = Error> {
+        log::info!("indexing missed blocks {} to {}", from, to);
+        let indexer = self.clone();
+        stream::iter_ok(from..=to).for_each(move |number| {
+            let indexer = indexer.clone();
+            let number: T::BlockNumber = number.saturated_into();
+            indexer
+                .client
+                .block_hash(Some(number.into()))
+                .map_err(Error::from)
+                .and_then(|hash| hash.ok_or(Error::UnknownBlock))
+                .and_then(move |hash| {
+                    indexer
+                        .index_block(hash, number)
+                        .and_then(move |_| indexer.indexed(hash, number))
+                })
+        })
+    }
+
+    /// Indexes the best block `header` and its ancestors which weren't
+    /// indexed yet, walking back its parents.
+    fn walk_back(&self, header: T::Header) -> impl Future<Item = (), Error = Error> {
+        let indexer = self.clone();
+        let headers = future::loop_fn(vec![header], move |mut headers| {
+            let (parent_hash, number) = {
+                let header = &headers[headers.len() - 1];
+                (*header.parent_hash(), (*header.number()).saturated_into::<u64>())
+            };
+            let done = match number.checked_sub(1) {
+                Some(parent) => indexer.is_indexed(&parent_hash, parent),
+                None => Ok(true),
+            };
+            match done {
+                Ok(true) => Either::A(future::ok(Loop::Break(headers))),
+                Ok(false) => {
+                    let parent = indexer
+                        .client
+                        .header(Some(parent_hash))
+                        .map_err(Error::from)
+                        .and_then(move |parent| {
+                            headers.push(parent.ok_or(Error::UnknownBlock)?);
+                            Ok(Loop::Continue(headers))
+                        });
+                    Either::B(parent)
+                }
+                Err(err) => Either::A(future::err(err)),
+            }
+        });
+        let indexer = self.clone();
+        headers.and_then(move |headers| {
+            if headers.len() > 1 {
+                log::info!("indexing {} missed blocks", headers.len() - 1);
+            }
+            stream::iter_ok(headers.into_iter().rev()).for_each(move |header| {
+                let indexer = indexer.clone();
+                let hash = header.hash();
+                let number = *header.number();
+                indexer
+                    .index_block(hash, number)
+                    .and_then(move |_| indexer.indexed(hash, number))
+            })
+        })
+    }
+
+    /// Replays the canonical blocks from `from` to `to` into the index.
+    ///
+    /// Fails if the parent hash of a block doesn't match the hash of the
+    /// preceding block, as the chain reorganized during the backfill.
+    pub fn backfill(&self, from: u64, to: u64) -> impl Future<Item = Backfill, Error = Error> {
+        let client = self.client.clone();
+        let headers = stream::iter_ok(from..=to).and_then(move |number| {
+            let number: T::BlockNumber = number.saturated_into();
+            let client2 = client.clone();
+            client
+                .block_hash(Some(number.into()))
+                .and_then(move |hash| {
+                    match hash {
+                        Some(hash) => Either::A(client2.header(Some(hash))),
+                        None => Either::B(future::ok(None)),
+                    }
+                })
+                .map_err(Error::from)
+                .and_then(|header| header.ok_or(Error::UnknownBlock))
+        });
+        let backfill = Backfill {
+            from,
+            to,
+            deposits: Vec::new(),
+        };
+        let indexer = self.clone();
+        headers
+            .fold((None, backfill), move |(parent, mut backfill), header: T::Header| {
+                if let Some(parent) = parent {
+                    if header.parent_hash() != &parent {
+                        return Either::A(future::err(Error::Discontinuity))
+                    }
+                }
+                let hash = header.hash();
+                let index = indexer.index_block(hash, *header.number()).map(move |deposits| {
+                    backfill.deposits.extend(deposits);
+                    (Some(hash), backfill)
+                });
+                Either::B(index)
+            })
+            .map(|(_, backfill)| {
+                log::info!(
+                    "backfilled blocks {} to {}, found {} deposits",
+                    backfill.from,
+                    backfill.to,
+                    backfill.deposits.len()
+                );
+                backfill
+            })
+    }
+
+    fn process_best(&self, header: T::Header) -> impl Future<Item = (), Error = Error> {
+        let number: u64 = (*header.number()).saturated_into();
+        *self.best.lock().unwrap() = number;
+        let indexer = self.clone();
+        self.catch_up(header).and_then(move |_| {
+            match indexer.policy {
+                CreditPolicy::Confirmations(confirmations) => {
+                    // A deposit in the best block has one confirmation.
+                    match (number + 1).checked_sub(std::cmp::max(confirmations, 1)) {
+                        Some(number) => Either::A(indexer.settle(number, false)),
+                        None => Either::B(future::ok(())),
+                    }
+                }
+                CreditPolicy::Finality => Either::B(future::ok(())),
+            }
+        })
+    }
+
+    fn process_finalized(&self, header: T::Header) -> impl Future<Item = (), Error = Error> {
+        let number: u64 = (*header.number()).saturated_into();
+        if let Err(err) = self.prune(number) {
+            return Either::A(future::err(err))
+        }
+        Either::B(self.settle(number, true))
+    }
+
+    /// Forgets the indexed blocks before the finalized block `number`.
+    ///
+    /// Blocks after the checkpoint are kept, so blocks missed while the
+    /// indexer was behind are still found.
+    fn prune(&self, number: u64) -> Result<(), Error> {
+        let checkpoint = self.checkpoint()?.map(|checkpoint| checkpoint.number);
+        let number = std::cmp::min(number, checkpoint.unwrap_or_default());
+        for entry in self.indexed.iter() {
+            let (key, _) = entry?;
+            if decode_number(&key) >= number {
+                break
+            }
+            self.indexed.remove(key)?;
+        }
+        Ok(())
+    }
+
+    /// Follows the chain, records deposits and settles them.
+    ///
+    /// Subscribes again after `RETRY_DELAY` if a subscription fails. Blocks
+    /// imported in the meantime are indexed with the next best block.
+    pub fn run(self) -> impl Future<Item = (), Error = ()> {
+        future::loop_fn(self, |indexer| {
+            indexer.follow().then(move |result| {
+                match result {
+                    Ok(()) => log::warn!("indexer subscriptions ended, resubscribing"),
+                    Err(err) => log::error!("indexer failed, resubscribing: {:?}", err),
+                }
+                Delay::new(Instant::now() + RETRY_DELAY)
+                    .then(move |_| Ok::<_, ()>(Loop::Continue(indexer)))
+            })
+        })
+    }
+
+    fn follow(&self) -> impl Future<Item = (), Error = Error> {
+        let best = self.client.subscribe_blocks();
+        let finalized = self.client.subscribe_finalized_blocks();
+        let indexer = self.clone();
+        best.join(finalized)
+            .map_err(Error::from)
+            .and_then(move |(best, finalized)| {
+                best.map(Head::Best)
+                    .select(finalized.map(Head::Finalized))
+                    .map_err(Error::from)
+                    .for_each(move |head| {
+                        match head {
+                            Head::Best(header) => Either::A(indexer.process_best(header)),
+                            Head::Finalized(header) => {
+                                Either::B(indexer.process_finalized(header))
+                            }
+                        }
+                    })
+            })
+    }
+}
+
+/// Returns the key of the block `hash` with the number `number`.
+fn block_key<T: Exchange>(number: u64, hash: &T::Hash) -> Vec<u8> {
+    let mut key = number.to_be_bytes().to_vec();
+    key.extend_from_slice(hash.as_ref());
+    key
+}
+
+/// Decodes the big endian block number or feed position at the start of
+/// `key`.
+fn decode_number(key: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&key[..8]);
+    u64::from_be_bytes(bytes)
+}
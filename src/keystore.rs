@@ -0,0 +1,330 @@
+//! Signing accounts managed by the shim.
+//!
+//! Secrets are loaded once at startup and afterwards only referenced by their
+//! key id or ss58 address, so they never have to be sent over the rpc
+//! interface.
+//!
+//! # Keystore file
+//!
+//! A keystore file starts with the magic bytes `sxks` followed by a version
+//! byte and the SCALE encoded `Vec<EncryptedKey>`. Each entry holds the suri
+//! of an account encrypted with ChaCha20Poly1305 under a key derived from the
+//! keystore passphrase with scrypt. All entries of a file share the same
+//! passphrase.
+//!
+//! A keystore opened from a file starts locked. Unlocking decrypts all entries
+//! into signing pairs, locking drops them again. The shim unlocks the
+//! keystore once at startup with a passphrase read from stdin. The file is
+//! only readable and writable by its owner. Decrypted suris are zeroized
+//! as soon as the pair has been created and the sr25519 secret keys zeroize
+//! themselves on drop.
+use crate::Error;
+use chacha20poly1305::{
+    aead::{
+        generic_array::GenericArray,
+        Aead,
+        NewAead,
+    },
+    ChaCha20Poly1305,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use rand::{
+    rngs::OsRng,
+    RngCore,
+};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use substrate_primitives::crypto::{
+    Pair,
+    Ss58Codec,
+};
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8; 4] = b"sxks";
+const VERSION: u8 = 1;
+
+/// Default scrypt cost parameters.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// An encrypted keystore entry.
+#[derive(Clone, Encode, Decode)]
+pub struct EncryptedKey {
+    /// Key id of the account.
+    pub id: String,
+    /// Ss58 address of the account.
+    pub address: String,
+    /// Scrypt `log_n` parameter.
+    pub log_n: u8,
+    /// Scrypt `r` parameter.
+    pub r: u32,
+    /// Scrypt `p` parameter.
+    pub p: u32,
+    /// Scrypt salt.
+    pub salt: [u8; 32],
+    /// ChaCha20Poly1305 nonce.
+    pub nonce: [u8; 12],
+    /// Encrypted suri including the authentication tag.
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedKey {
+    fn cipher(
+        passphrase: &str,
+        salt: &[u8],
+        log_n: u8,
+        r: u32,
+        p: u32,
+    ) -> Result<ChaCha20Poly1305, Error> {
+        let params =
+            scrypt::ScryptParams::new(log_n, r, p).map_err(|_| Error::InvalidKeystore)?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key[..])
+            .map_err(|_| Error::InvalidKeystore)?;
+        Ok(ChaCha20Poly1305::new(*GenericArray::from_slice(&key[..])))
+    }
+
+    fn encrypt(
+        id: &str,
+        address: String,
+        suri: &str,
+        passphrase: &str,
+    ) -> Result<Self, Error> {
+        let mut salt = [0u8; 32];
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let cipher = Self::cipher(passphrase, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+        let ciphertext = cipher
+            .encrypt(GenericArray::from_slice(&nonce), suri.as_bytes())
+            .map_err(|_| Error::InvalidKeystore)?;
+        Ok(Self {
+            id: id.to_string(),
+            address,
+            log_n: SCRYPT_LOG_N,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    fn decrypt(&self, passphrase: &str) -> Result<Zeroizing<String>, Error> {
+        let cipher = Self::cipher(passphrase, &self.salt, self.log_n, self.r, self.p)?;
+        let suri = cipher
+            .decrypt(GenericArray::from_slice(&self.nonce), &self.ciphertext[..])
+            .map_err(|_| Error::InvalidPassphrase)?;
+        String::from_utf8(suri)
+            .map(Zeroizing::new)
+            .map_err(|err| {
+                let mut bytes = err.into_bytes();
+                zeroize::Zeroize::zeroize(&mut bytes);
+                Error::InvalidKeystore
+            })
+    }
+}
+
+/// Named signing accounts.
+pub struct Keystore<P: Pair> {
+    path: Option<PathBuf>,
+    encrypted: Vec<EncryptedKey>,
+    pairs: HashMap<String, P>,
+}
+
+impl<P: Pair> Default for Keystore<P> {
+    fn default() -> Self {
+        Self {
+            path: None,
+            encrypted: Default::default(),
+            pairs: Default::default(),
+        }
+    }
+}
+
+impl<P: Pair> Keystore<P>
+where
+    P::Public: Ss58Codec,
+{
+    /// Creates an empty in-memory `Keystore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the keystore file at `path`.
+    ///
+    /// The returned keystore is locked. If the file doesn't exist it is created
+    /// when the first key is added.
+    pub fn open<A: AsRef<Path>>(path: A) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let encrypted = match std::fs::read(&path) {
+            Ok(bytes) => Self::decode_file(&bytes)?,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            encrypted,
+            pairs: Default::default(),
+        })
+    }
+
+    fn decode_file(bytes: &[u8]) -> Result<Vec<EncryptedKey>, Error> {
+        if bytes.len() < 5 || &bytes[..4] != MAGIC || bytes[4] != VERSION {
+            return Err(Error::InvalidKeystore)
+        }
+        Decode::decode(&mut &bytes[5..]).map_err(|_| Error::InvalidKeystore)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        if let Some(path) = &self.path {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            self.encrypted.encode_to(&mut bytes);
+            let tmp = path.with_extension("tmp");
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&tmp)?;
+            #[cfg(unix)]
+            file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the keystore has encrypted keys which are not
+    /// available for signing.
+    pub fn is_locked(&self) -> bool {
+        !self.encrypted.is_empty() && self.pairs.is_empty()
+    }
+
+    /// Decrypts all keys of the keystore with `passphrase`.
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), Error> {
+        let mut pairs = HashMap::new();
+        for key in &self.encrypted {
+            let suri = key.decrypt(passphrase)?;
+            let pair = P::from_string(&suri, None).map_err(|_| Error::InvalidSURI)?;
+            pairs.insert(key.id.clone(), pair);
+        }
+        self.pairs.extend(pairs);
+        Ok(())
+    }
+
+    /// Drops all decrypted keys from memory.
+    pub fn lock(&mut self) {
+        self.pairs.clear();
+    }
+
+    /// Encrypts `suri` with `passphrase` and adds it to the keystore file
+    /// under the key id `id`.
+    ///
+    /// The passphrase must match the passphrase of the existing keys.
+    pub fn add(&mut self, id: &str, suri: &str, passphrase: &str) -> Result<P::Public, Error> {
+        if self.encrypted.iter().any(|key| key.id == id) {
+            return Err(Error::DuplicateKeyId)
+        }
+        if let Some(key) = self.encrypted.first() {
+            key.decrypt(passphrase)?;
+        }
+        let pair = P::from_string(suri, None).map_err(|_| Error::InvalidSURI)?;
+        let public = pair.public();
+        let key = EncryptedKey::encrypt(id, public.to_ss58check(), suri, passphrase)?;
+        self.encrypted.push(key);
+        self.save()?;
+        Ok(public)
+    }
+
+    /// Adds the account for `suri` under the key id `id` without persisting
+    /// it.
+    pub fn insert_suri(&mut self, id: &str, suri: &str) -> Result<P::Public, Error> {
+        let pair = P::from_string(suri, None).map_err(|_| Error::InvalidSURI)?;
+        let public = pair.public();
+        self.pairs.insert(id.to_string(), pair);
+        Ok(public)
+    }
+
+    /// Looks up a managed account by key id or ss58 address.
+    pub fn get(&self, account: &str) -> Result<&P, Error> {
+        if let Some(pair) = self.pairs.get(account) {
+            return Ok(pair)
+        }
+        let public = <P::Public as Ss58Codec>::from_string(account)
+            .map_err(|_| Error::UnknownAccount)?;
+        if let Some(pair) = self.pairs.values().find(|pair| pair.public() == public) {
+            return Ok(pair)
+        }
+        let address = public.to_ss58check();
+        if self.encrypted.iter().any(|key| key.id == account || key.address == address) {
+            return Err(Error::KeystoreLocked)
+        }
+        Err(Error::UnknownAccount)
+    }
+
+    /// Returns the key ids and public keys of all unlocked accounts.
+    pub fn accounts(&self) -> impl Iterator<Item = (&str, P::Public)> {
+        self.pairs.iter().map(|(id, pair)| (id.as_str(), pair.public()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use substrate_primitives::sr25519;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    fn keystore_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        std::fs::remove_file(&path).ok();
+        path
+    }
+
+    #[test]
+    fn test_keystore_roundtrip() {
+        let path = keystore_path("keystore-roundtrip");
+        let mut keystore = Keystore::<sr25519::Pair>::open(&path).unwrap();
+        let alice = keystore.add("alice", "//Alice", PASSPHRASE).unwrap();
+
+        let mut keystore = Keystore::<sr25519::Pair>::open(&path).unwrap();
+        assert!(keystore.is_locked());
+        assert!(keystore.get("alice").is_err());
+        assert!(keystore.unlock("wrong passphrase").is_err());
+        keystore.unlock(PASSPHRASE).unwrap();
+        assert_eq!(keystore.get("alice").unwrap().public(), alice);
+        assert_eq!(keystore.get(&alice.to_ss58check()).unwrap().public(), alice);
+
+        keystore.lock();
+        assert!(keystore.is_locked());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keystore_rejects_other_passphrase() {
+        let path = keystore_path("keystore-passphrase");
+        let mut keystore = Keystore::<sr25519::Pair>::open(&path).unwrap();
+        keystore.add("alice", "//Alice", PASSPHRASE).unwrap();
+        assert!(keystore.add("bob", "//Bob", "other").is_err());
+        assert!(keystore.add("alice", "//Bob", PASSPHRASE).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
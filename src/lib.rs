@@ -2,34 +2,241 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-use futures::future::{self, Future};
-use jsonrpc_core::Error as RpcError;
+use futures::{
+    future::{self, Either, Future, Loop},
+    stream::{self, Stream},
+};
+use jsonrpc_core::{
+    Error as RpcError,
+    ErrorCode,
+};
+use jsonrpc_core_client::RpcError as ClientError;
 use jsonrpc_derive::rpc;
-use parity_scale_codec::Codec;
-use sr_primitives::traits::StaticLookup;
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+use parity_scale_codec::{Codec, Decode, Encode};
+use serde_json::json;
+use sr_primitives::{
+    generic::Era,
+    traits::{Header, SaturatedConversion, Saturating, StaticLookup, Zero},
+};
+use std::sync::{
+    Arc,
+    RwLock,
 };
 use substrate_primitives::crypto::{
+    Derive,
     Pair,
     Ss58Codec,
 };
 use substrate_subxt::{
     Client,
     srml::{
-        balances::{Balances, BalancesCalls, BalancesStore},
-        system::System,
+        balances::{Balances, BalancesStore},
+        system::{System, SystemStore},
     }
 };
 
+mod addresses;
+mod amount;
+mod balance;
+mod db;
+mod extrinsic;
+mod indexer;
+mod keystore;
+mod metadata;
+mod node;
+mod nonce;
+pub mod offline;
+mod outbox;
+mod pubsub;
+mod storage;
+mod tracker;
+mod transfer;
+mod webhook;
+
+pub use addresses::{
+    DepositAddress,
+    DepositAddresses,
+};
+pub use amount::{
+    Amount,
+    Denomination,
+};
+pub use balance::{
+    AccountBalance,
+    AccountsBalances,
+    At,
+    BatchEntry,
+    Lock,
+};
+use db::Batch;
+pub use db::Db;
+pub use extrinsic::{
+    CheckEra,
+    CheckGenesis,
+};
+pub use indexer::{
+    Backfill,
+    Checkpoint,
+    Credit,
+    CreditPolicy,
+    CreditReady,
+    Deposit,
+    DepositState,
+    WatchedAddress,
+};
+use indexer::Indexer;
+pub use keystore::Keystore;
+use metadata::Metadata;
+pub use node::Node;
+use nonce::{
+    is_nonce_rejection,
+    NonceManager,
+};
+pub use transfer::{
+    BlockInfo,
+    FeeEstimate,
+    Mortality,
+    TransferOptions,
+    TransferResult,
+};
+use transfer::{
+    Requests,
+    Signing,
+};
+pub use tracker::{
+    DispatchFailure,
+    Outcome,
+    TransactionStatus,
+    TxState,
+    Wait,
+};
+use tracker::Tracker;
+use offline::{
+    SignedTransfer,
+    UnsignedTransfer,
+};
+use outbox::{
+    Outbox,
+    Withdrawal,
+};
+pub use webhook::{
+    signature,
+    WebhookConfig,
+    SIGNATURE_HEADER,
+};
+use webhook::Webhooks;
+pub use pubsub::{
+    Meta,
+    PubSub,
+    Subscriptions,
+};
+
+/// Maximum number of addresses of a batch balance query.
+const MAX_BATCH_ADDRESSES: usize = 256;
+/// Maximum number of blocks replayed by one backfill.
+const MAX_BACKFILL_BLOCKS: u64 = 1000;
+
 /// Trait defining all chain specific data.
 pub trait Exchange: System + Balances + 'static {
     /// Pair to use for signing.
     type Pair: Pair;
+    /// Runtime call.
+    type Call: Codec + Clone + Send + Sync;
+
+    /// Returns the call transferring `amount` to `dest`.
+    fn transfer_call(
+        dest: <<Self as System>::Lookup as StaticLookup>::Source,
+        amount: <Self as Balances>::Balance,
+    ) -> Self::Call;
+
+    /// Returns the amount `call` transfers, `None` if it isn't a transfer.
+    fn transfer_amount(call: &Self::Call) -> Option<<Self as Balances>::Balance>;
+
+    /// Returns the outcome of an extrinsic if `event` is a system
+    /// `ExtrinsicSuccess` or `ExtrinsicFailed` event.
+    ///
+    /// The failure of a failed extrinsic only needs the module and error
+    /// index, the shim resolves their names using the metadata of the runtime
+    /// the node ran the block with.
+    fn extrinsic_outcome(event: &<Self as System>::Event) -> Option<Outcome>;
+
+    /// Returns the balance credit if `event` credits an account, like a
+    /// balance transfer.
+    ///
+    /// Only credits announced by an event can be detected. Runtimes credit
+    /// some balances without one, like staking rewards or balances set by
+    /// root, so the balance of a watched account may exceed its deposits.
+    fn balance_credit(event: &<Self as System>::Event) -> Option<Credit<Self>>;
+
+    /// Returns the signed extensions of an extrinsic with the nonce `nonce`
+    /// valid in the era `era` starting at the block `era_hash` paying the tip
+    /// `tip`.
+    ///
+    /// The extensions are used to derive the signing payload, so they must
+    /// return their additional signed data without access to the runtime
+    /// storage, see `extrinsic::CheckGenesis` and `extrinsic::CheckEra`.
+    fn signed_extra(
+        nonce: <Self as System>::Index,
+        era: Era,
+        tip: <Self as Balances>::Balance,
+        genesis_hash: <Self as System>::Hash,
+        era_hash: <Self as System>::Hash,
+    ) -> Self::SignedExtra;
+
+    /// Returns the dispatch weight of `call`, used if the node has no payment
+    /// rpc.
+    fn call_weight(call: &Self::Call) -> u32;
+
+    /// Returns the fee charged for the dispatch weight `weight`, used if the
+    /// node has no payment rpc.
+    fn weight_to_fee(weight: u32) -> <Self as Balances>::Balance;
+}
+
+/// Configuration of the shim.
+pub struct Config<T: Exchange> {
+    /// Public key deposit addresses are derived from.
+    pub deposit_master: Option<<T::Pair as Pair>::Public>,
+    /// Number of blocks a signed transfer stays valid for, `0` for immortal
+    /// transfers.
+    ///
+    /// The era starts at the finalized head, so the period must exceed the
+    /// number of blocks finality lags behind.
+    pub era_period: u64,
+    /// Tip paid by transfers not specifying one.
+    pub tip: <T as Balances>::Balance,
+    /// Highest tip a transfer may pay.
+    pub max_tip: <T as Balances>::Balance,
+    /// Decimals and symbol of the token.
+    pub denomination: Denomination,
+    /// When deposits are ready to be credited.
+    pub credit_policy: CreditPolicy,
+    /// Webhooks notified of deposits and withdrawals.
+    pub webhooks: WebhookConfig,
+    /// Number of subscriptions a WebSocket connection may hold.
+    pub max_subscriptions: usize,
+    /// Number of balance subscriptions of all connections together, each
+    /// queries the node at every best block.
+    pub max_balance_subscriptions: usize,
+}
+
+impl<T: Exchange> Default for Config<T> {
+    fn default() -> Self {
+        Self {
+            deposit_master: None,
+            era_period: 64,
+            tip: Zero::zero(),
+            max_tip: Zero::zero(),
+            denomination: Default::default(),
+            credit_policy: Default::default(),
+            webhooks: Default::default(),
+            max_subscriptions: 16,
+            max_balance_subscriptions: 1024,
+        }
+    }
 }
 
 /// Error enum
+#[derive(Debug)]
 pub enum Error {
     /// Invalid SURI.
     InvalidSURI,
@@ -37,6 +244,250 @@ pub enum Error {
     InvalidSS58,
     /// Invalid balance.
     InvalidBalance,
+    /// Unknown account.
+    UnknownAccount,
+    /// Keystore is locked.
+    KeystoreLocked,
+    /// Invalid keystore passphrase.
+    InvalidPassphrase,
+    /// Invalid keystore file.
+    InvalidKeystore,
+    /// Duplicate key id.
+    DuplicateKeyId,
+    /// No deposit master configured.
+    NoDepositMaster,
+    /// Invalid signature.
+    InvalidSignature,
+    /// Invalid offline signing payload.
+    InvalidPayload,
+    /// Unknown transaction.
+    UnknownTransaction,
+    /// Invalid extrinsic hash.
+    InvalidHash,
+    /// Extrinsic was dropped from the transaction pool.
+    Dropped,
+    /// Era of the extrinsic ended before it was included.
+    Expired,
+    /// Unknown block.
+    UnknownBlock,
+    /// Amount has more fractional digits than the token has decimals.
+    ExcessPrecision,
+    /// Amount is too large.
+    AmountOverflow,
+    /// Transfer would leave the sender below the existential deposit.
+    WouldReapSender,
+    /// Transfer would create the recipient with less than the existential
+    /// deposit.
+    BelowExistentialDeposit,
+    /// Transferable balance doesn't cover the fee of a sweep.
+    NothingToSweep,
+    /// Tip exceeds the configured ceiling.
+    TipTooHigh,
+    /// Tip doesn't exceed the tip of the replaced extrinsic.
+    TipTooLow,
+    /// Extrinsic is no longer waiting in the transaction pool.
+    NotPending,
+    /// A transfer with the same request id is in progress.
+    RequestPending,
+    /// The request id was used for a different transfer.
+    RequestMismatch,
+    /// The node can't be reached.
+    NodeUnavailable(String),
+    /// A request to the node timed out.
+    Timeout,
+    /// The transaction pool rejected the extrinsic for the given reason.
+    PoolRejected(PoolError),
+    /// The extrinsic was included but failed at dispatch.
+    DispatchFailed {
+        /// Hash of the extrinsic.
+        hash: String,
+        /// Error the extrinsic failed with.
+        failure: DispatchFailure,
+    },
+    /// The sender can't pay the transfer and its fee.
+    InsufficientFunds {
+        /// Amount and fee of the transfer in base units, if known.
+        required: Option<u128>,
+        /// Transferable balance of the sender in base units, if known.
+        available: Option<u128>,
+    },
+    /// Subxt error.
+    Subxt(substrate_subxt::Error),
+    /// Io error.
+    Io(std::io::Error),
+    /// Database error.
+    Database(sled::Error),
+    /// Corrupted database entry.
+    CorruptedDatabase,
+    /// Unexpected response of the node.
+    InvalidResponse,
+    /// The parent hash of a block doesn't match the preceding block.
+    Discontinuity,
+    /// Webhook url isn't a valid http or https url.
+    InvalidWebhookUrl,
+    /// Webhook urls are configured without a secret to sign with.
+    EmptyWebhookSecret,
+    /// The connection reached its subscription limit.
+    TooManySubscriptions,
+    /// The shim reached its balance subscription limit.
+    TooManyBalanceSubscriptions,
+    /// A batch balance query has more addresses than allowed.
+    TooManyAddresses,
+    /// A backfill range is empty or spans more blocks than allowed.
+    InvalidBackfillRange,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Reason the transaction pool rejected an extrinsic for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PoolError {
+    /// The runtime considers the extrinsic invalid for the given reason, like
+    /// `Stale` or `Future` for a nonce which is used or too far ahead.
+    Invalid(String),
+    /// The validity of the extrinsic can't be determined.
+    UnknownValidity,
+    /// The extrinsic is temporarily banned.
+    TemporarilyBanned,
+    /// The pool already holds the extrinsic.
+    AlreadyImported,
+    /// The pool holds an extrinsic with the same nonce and a higher priority.
+    TooLowPriority,
+    /// Other error of the transaction pool with its error code.
+    Other(i64),
+}
+
+/// Error codes of the transaction pool.
+const POOL_INVALID_TX: i64 = 1010;
+const POOL_UNKNOWN_VALIDITY: i64 = 1011;
+const POOL_TEMPORARILY_BANNED: i64 = 1012;
+const POOL_ALREADY_IMPORTED: i64 = 1013;
+const POOL_TOO_LOW_PRIORITY: i64 = 1014;
+/// Last error code reserved for the transaction pool.
+const POOL_LAST: i64 = 1019;
+
+impl PoolError {
+    /// Returns the pool error with the error code `code` and the error data
+    /// `data` the node responded with.
+    fn from_rpc(code: i64, data: Option<serde_json::Value>) -> Self {
+        match code {
+            POOL_INVALID_TX => {
+                let reason = match data {
+                    Some(serde_json::Value::String(reason)) => reason,
+                    Some(data) => data.to_string(),
+                    None => String::new(),
+                };
+                PoolError::Invalid(reason)
+            }
+            POOL_UNKNOWN_VALIDITY => PoolError::UnknownValidity,
+            POOL_TEMPORARILY_BANNED => PoolError::TemporarilyBanned,
+            POOL_ALREADY_IMPORTED => PoolError::AlreadyImported,
+            POOL_TOO_LOW_PRIORITY => PoolError::TooLowPriority,
+            code => PoolError::Other(code),
+        }
+    }
+
+    /// Returns the error code of the transaction pool.
+    pub fn code(&self) -> i64 {
+        match self {
+            PoolError::Invalid(_) => POOL_INVALID_TX,
+            PoolError::UnknownValidity => POOL_UNKNOWN_VALIDITY,
+            PoolError::TemporarilyBanned => POOL_TEMPORARILY_BANNED,
+            PoolError::AlreadyImported => POOL_ALREADY_IMPORTED,
+            PoolError::TooLowPriority => POOL_TOO_LOW_PRIORITY,
+            PoolError::Other(code) => *code,
+        }
+    }
+
+    /// Returns a short description of the rejection.
+    pub fn reason(&self) -> String {
+        match self {
+            PoolError::Invalid(reason) => reason.clone(),
+            PoolError::Other(code) => format!("Error {}", code),
+            error => format!("{:?}", error),
+        }
+    }
+}
+
+impl Error {
+    /// Returns `true` if the transaction pool refused a submitted extrinsic,
+    /// so it can't be included.
+    ///
+    /// Other errors of a submission, like a timeout, leave open whether the
+    /// extrinsic reached the pool.
+    fn is_rejection(&self) -> bool {
+        match self {
+            Error::PoolRejected(PoolError::AlreadyImported) => false,
+            Error::PoolRejected(_) | Error::InsufficientFunds { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<substrate_subxt::Error> for Error {
+    fn from(error: substrate_subxt::Error) -> Self {
+        let err = match error {
+            substrate_subxt::Error::Io(err) => return Error::NodeUnavailable(err.to_string()),
+            substrate_subxt::Error::Rpc(ClientError::JsonRpcError(err)) => err,
+            substrate_subxt::Error::Rpc(ClientError::Timeout) => return Error::Timeout,
+            substrate_subxt::Error::Rpc(err) => return Error::NodeUnavailable(err.to_string()),
+            error => return Error::Subxt(error),
+        };
+        let code = match err.code {
+            // Only the transaction pool uses the error codes 1010 to 1019.
+            ErrorCode::ServerError(code) if code >= POOL_INVALID_TX && code <= POOL_LAST => code,
+            _ => {
+                let err = ClientError::JsonRpcError(err);
+                return Error::Subxt(substrate_subxt::Error::Rpc(err))
+            }
+        };
+        match PoolError::from_rpc(code, err.data) {
+            PoolError::Invalid(ref reason) if reason == "Payment" => {
+                Error::InsufficientFunds {
+                    required: None,
+                    available: None,
+                }
+            }
+            error => Error::PoolRejected(error),
+        }
+    }
+}
+
+impl From<sled::Error> for Error {
+    fn from(error: sled::Error) -> Self {
+        Error::Database(error)
+    }
+}
+
+/// Error code of `Error::NodeUnavailable`.
+pub const NODE_UNAVAILABLE: i64 = -32010;
+/// Error code of `Error::Timeout`.
+pub const TIMEOUT: i64 = -32011;
+/// Error code of `Error::PoolRejected`.
+pub const POOL_REJECTED: i64 = -32012;
+/// Error code of `Error::DispatchFailed`.
+pub const DISPATCH_FAILED: i64 = -32013;
+/// Error code of `Error::InsufficientFunds`.
+pub const INSUFFICIENT_FUNDS: i64 = -32014;
+/// Error code of `Error::Discontinuity`.
+pub const DISCONTINUITY: i64 = -32015;
+/// Error code of `Error::TooManySubscriptions` and
+/// `Error::TooManyBalanceSubscriptions`.
+pub const TOO_MANY_SUBSCRIPTIONS: i64 = -32016;
+/// Error code of `Error::InvalidWebhookUrl`.
+pub const INVALID_WEBHOOK_URL: i64 = -32017;
+
+/// Returns an error with the code `code` and the structured `data`.
+fn chain_error(code: i64, message: &str, data: serde_json::Value) -> RpcError {
+    RpcError {
+        code: ErrorCode::ServerError(code),
+        message: message.into(),
+        data: Some(data),
+    }
 }
 
 impl From<Error> for RpcError {
@@ -44,7 +495,121 @@ impl From<Error> for RpcError {
         let msg = match err {
             Error::InvalidSURI => "Expected a suri encoded private key.",
             Error::InvalidSS58 => "Expected a ss58 encoded public key.",
-            Error::InvalidBalance => "Expected a numeric string.",
+            Error::InvalidBalance => {
+                "Expected an amount in base units, hex or whole units with a decimal point."
+            }
+            Error::UnknownAccount => "Expected a key id or ss58 address of a managed account.",
+            Error::KeystoreLocked => "The keystore is locked.",
+            Error::InvalidPassphrase => "Invalid keystore passphrase.",
+            Error::InvalidKeystore => "Invalid keystore file.",
+            Error::DuplicateKeyId => "Key id already exists.",
+            Error::NoDepositMaster => "No deposit master configured.",
+            Error::InvalidSignature => "Invalid signature.",
+            Error::InvalidPayload => "Expected a hex encoded transfer for this chain.",
+            Error::UnknownTransaction => "Unknown transaction.",
+            Error::InvalidHash => "Expected a hex encoded extrinsic hash.",
+            Error::Dropped => "The extrinsic was dropped from the transaction pool.",
+            Error::Expired => "The extrinsic expired before it was included.",
+            Error::UnknownBlock => "Unknown block.",
+            Error::ExcessPrecision => "The amount has more decimals than the token.",
+            Error::AmountOverflow => "The amount is too large.",
+            Error::WouldReapSender => {
+                "The transfer would leave the sender below the existential deposit."
+            }
+            Error::BelowExistentialDeposit => {
+                "The transfer would create the recipient below the existential deposit."
+            }
+            Error::NothingToSweep => "The transferable balance doesn't cover the fee.",
+            Error::TipTooHigh => "The tip exceeds the configured maximum.",
+            Error::TipTooLow => "The tip must exceed the tip of the pending transfer.",
+            Error::NotPending => "The transfer is no longer waiting to be included.",
+            Error::RequestPending => "A transfer with this request id is in progress.",
+            Error::RequestMismatch => "The request id was used for a different transfer.",
+            Error::NodeUnavailable(reason) => {
+                log::error!("node unavailable: {}", reason);
+                let data = json!({ "reason": reason });
+                return chain_error(NODE_UNAVAILABLE, "The node is unavailable.", data)
+            }
+            Error::Timeout => {
+                let data = json!({});
+                return chain_error(TIMEOUT, "The request to the node timed out.", data)
+            }
+            Error::PoolRejected(error) => {
+                let data = json!({ "code": error.code(), "reason": error.reason() });
+                let msg = "The transaction pool rejected the extrinsic.";
+                return chain_error(POOL_REJECTED, msg, data)
+            }
+            Error::DispatchFailed { hash, failure } => {
+                let data = json!({
+                    "hash": hash,
+                    "module_index": failure.module_index,
+                    "error_index": failure.error_index,
+                    "module": failure.module,
+                    "error": failure.error,
+                });
+                let msg = "The extrinsic failed at dispatch.";
+                return chain_error(DISPATCH_FAILED, msg, data)
+            }
+            Error::InsufficientFunds {
+                required,
+                available,
+            } => {
+                // Balances exceed the range of json numbers.
+                let data = json!({
+                    "required": required.map(|amount| amount.to_string()),
+                    "available": available.map(|amount| amount.to_string()),
+                });
+                let msg = "The sender can't pay the transfer and its fee.";
+                return chain_error(INSUFFICIENT_FUNDS, msg, data)
+            }
+            Error::Subxt(err) => {
+                log::error!("{:?}", err);
+                return RpcError::internal_error()
+            }
+            Error::Io(err) => {
+                log::error!("{:?}", err);
+                return RpcError::internal_error()
+            }
+            Error::Database(err) => {
+                log::error!("{:?}", err);
+                return RpcError::internal_error()
+            }
+            Error::CorruptedDatabase => {
+                log::error!("corrupted database entry");
+                return RpcError::internal_error()
+            }
+            Error::InvalidResponse => {
+                log::error!("unexpected response of the node");
+                return RpcError::internal_error()
+            }
+            Error::Discontinuity => {
+                let msg = "The chain was reorganized while replaying it, retry.";
+                return chain_error(DISCONTINUITY, msg, json!({}))
+            }
+            Error::InvalidWebhookUrl => {
+                let msg = "Expected a http or https webhook url.";
+                return chain_error(INVALID_WEBHOOK_URL, msg, json!({}))
+            }
+            Error::EmptyWebhookSecret => "Expected a webhook secret.",
+            Error::TooManySubscriptions => {
+                let msg = "The connection reached its subscription limit.";
+                return chain_error(TOO_MANY_SUBSCRIPTIONS, msg, json!({}))
+            }
+            Error::TooManyBalanceSubscriptions => {
+                let msg = "The shim reached its balance subscription limit.";
+                return chain_error(TOO_MANY_SUBSCRIPTIONS, msg, json!({}))
+            }
+            Error::TooManyAddresses => {
+                let msg = format!("Expected at most {} addresses.", MAX_BATCH_ADDRESSES);
+                return RpcError::invalid_params(msg)
+            }
+            Error::InvalidBackfillRange => {
+                let msg = format!(
+                    "Expected `from` not after `to` and at most {} blocks.",
+                    MAX_BACKFILL_BLOCKS
+                );
+                return RpcError::invalid_params(msg)
+            }
         };
         RpcError::invalid_params(msg)
     }
@@ -53,55 +618,1058 @@ impl From<Error> for RpcError {
 /// The rpc interface for wallet applications.
 #[rpc(server)]
 pub trait Rpc<T: Exchange> {
-    /// Query the balance of an account.
-    #[rpc(name = "account_balance", returns = "String")]
+    /// Query the free balance of an account.
+    #[rpc(name = "account_balance", returns = "Amount")]
     fn account_balance(
         &self,
         from: String,
-    ) -> Box<dyn Future<Item = String, Error = RpcError> + Send>;
+    ) -> Box<dyn Future<Item = Amount, Error = RpcError> + Send>;
+
+    /// Query the free, reserved, locked and transferable balance of an
+    /// account.
+    ///
+    /// `at` is the number or hash of the block to query, defaulting to the
+    /// best block. The block used is part of the result.
+    #[rpc(name = "account_balance_details", returns = "AccountBalance")]
+    fn account_balance_details(
+        &self,
+        of: String,
+        at: Option<At>,
+    ) -> Box<dyn Future<Item = AccountBalance, Error = RpcError> + Send>;
 
-    /// Transfer the given amount of balance from one account to an other.
-    #[rpc(name = "transfer_balance", returns = "()")]
+    /// Query the balances of many accounts at one block.
+    ///
+    /// Addresses which can't be queried are reported in their entry without
+    /// failing the batch. The balances are read with a single storage query of
+    /// at most 256 addresses.
+    #[rpc(name = "accounts_balances", returns = "AccountsBalances")]
+    fn accounts_balances(
+        &self,
+        addresses: Vec<String>,
+        at: Option<At>,
+    ) -> Box<dyn Future<Item = AccountsBalances, Error = RpcError> + Send>;
+
+    /// Transfer the given amount of balance from a managed account to an other.
+    ///
+    /// `from` is the key id or ss58 address of an account in the keystore.
+    #[rpc(name = "transfer_balance", returns = "TransferResult")]
     fn transfer_balance(
         &self,
         from: String,
         to: String,
         amount: String,
-    ) -> Box<dyn Future<Item = (), Error = RpcError> + Send>;
+        options: Option<TransferOptions>,
+    ) -> Box<dyn Future<Item = TransferResult, Error = RpcError> + Send>;
+
+    /// Transfer the whole transferable balance of a managed account minus the
+    /// fee to an other account.
+    ///
+    /// The existential deposit stays behind unless `keep_alive` is unset in
+    /// `options`, which allows emptying the account. The result reports the
+    /// swept amount and the fee paid.
+    #[rpc(name = "transfer_sweep", returns = "TransferResult")]
+    fn transfer_sweep(
+        &self,
+        from: String,
+        to: String,
+        options: Option<TransferOptions>,
+    ) -> Box<dyn Future<Item = TransferResult, Error = RpcError> + Send>;
+
+    /// Estimate the fee of a transfer.
+    ///
+    /// Builds the extrinsic `transfer_balance` would submit and queries the
+    /// node for its weight and fee. The fee is split into its components using
+    /// the fee constants in the metadata of the node.
+    #[rpc(name = "estimate_transfer_fee", returns = "FeeEstimate")]
+    fn estimate_transfer_fee(
+        &self,
+        from: String,
+        to: String,
+        amount: String,
+        options: Option<TransferOptions>,
+    ) -> Box<dyn Future<Item = FeeEstimate, Error = RpcError> + Send>;
+
+    /// Replace a pending transfer with one paying a higher tip.
+    ///
+    /// `hash` is the extrinsic hash of a transfer still waiting in the
+    /// transaction pool. The replacement reuses its nonce and era, so at most
+    /// one of them is included and the other is reported as dropped.
+    #[rpc(name = "transfer_bump_tip", returns = "TransferResult")]
+    fn transfer_bump_tip(
+        &self,
+        hash: String,
+        tip: String,
+    ) -> Box<dyn Future<Item = TransferResult, Error = RpcError> + Send>;
+
+    /// Lock the keystore, zeroizing all decrypted keys in memory.
+    ///
+    /// The keystore is unlocked again by restarting the shim.
+    #[rpc(name = "keystore_lock")]
+    fn keystore_lock(&self) -> Result<(), RpcError>;
+
+    /// Derive a new deposit address for a customer.
+    ///
+    /// The address is watched for deposits, labelled with the customer id.
+    #[rpc(name = "account_new_deposit_address")]
+    fn account_new_deposit_address(
+        &self,
+        customer_id: String,
+    ) -> Result<DepositAddress, RpcError>;
+
+    /// Watch an address for deposits.
+    ///
+    /// `label` is returned with every deposit to the address.
+    #[rpc(name = "deposit_watch_address")]
+    fn deposit_watch_address(
+        &self,
+        address: String,
+        label: Option<String>,
+    ) -> Result<WatchedAddress, RpcError>;
+
+    /// Stop watching an address for deposits.
+    ///
+    /// Returns `false` if the address wasn't watched.
+    #[rpc(name = "deposit_unwatch_address")]
+    fn deposit_unwatch_address(&self, address: String) -> Result<bool, RpcError>;
+
+    /// List the deposits to a watched address, or to all watched addresses.
+    #[rpc(name = "deposit_list")]
+    fn deposit_list(&self, address: Option<String>) -> Result<Vec<Deposit>, RpcError>;
+
+    /// List the deposits which became ready to be credited after `cursor`.
+    ///
+    /// Deposits are ready according to the configured credit policy. A
+    /// credited deposit whose block is retracted later is listed again in the
+    /// `orphaned` state. Pass the returned cursor to continue reading, or no
+    /// cursor to read from the start.
+    #[rpc(name = "deposit_credit_ready")]
+    fn deposit_credit_ready(&self, cursor: Option<u64>) -> Result<CreditReady, RpcError>;
+
+    /// Query the last best block processed by the deposit indexer.
+    #[rpc(name = "deposit_checkpoint")]
+    fn deposit_checkpoint(&self) -> Result<Option<Checkpoint>, RpcError>;
+
+    /// Replay the canonical blocks from `from` to `to` into the deposit index.
+    ///
+    /// Records deposits to watched addresses made before they were watched or
+    /// before the indexer started. `to` defaults to the checkpoint. Deposits
+    /// recorded before aren't recorded again. A backfill replays at most 1000
+    /// blocks, longer ranges are replayed in several calls.
+    #[rpc(name = "deposit_backfill", returns = "Backfill")]
+    fn deposit_backfill(
+        &self,
+        from: u64,
+        to: Option<u64>,
+    ) -> Box<dyn Future<Item = Backfill, Error = RpcError> + Send>;
+
+    /// Build the unsigned payload of a transfer for offline signing.
+    ///
+    /// `from` is the ss58 address of the signing account. Returns the hex
+    /// encoded `UnsignedTransfer`. Its nonce stays reserved until the signed
+    /// transfer is submitted or its era ends.
+    #[rpc(name = "transfer_payload", returns = "String")]
+    fn transfer_payload(
+        &self,
+        from: String,
+        to: String,
+        amount: String,
+    ) -> Box<dyn Future<Item = String, Error = RpcError> + Send>;
+
+    /// Verify and broadcast a transfer signed offline.
+    ///
+    /// `transfer` is the hex encoded `SignedTransfer`. Returns the extrinsic
+    /// hash.
+    #[rpc(name = "submit_signed_transfer", returns = "String")]
+    fn submit_signed_transfer(
+        &self,
+        transfer: String,
+    ) -> Box<dyn Future<Item = String, Error = RpcError> + Send>;
+
+    /// Query the status of an extrinsic submitted by the shim.
+    #[rpc(name = "transaction_status")]
+    fn transaction_status(&self, hash: String) -> Result<TransactionStatus, RpcError>;
 }
 
 /// The implementation of the Rpc trait.
 pub struct RpcImpl<T: Exchange> {
     client: Client<T>,
-    nonces: Arc<Mutex<HashMap<T::AccountId, T::Index>>>,
+    node: Node<T>,
+    db: Db,
+    keystore: Arc<RwLock<Keystore<T::Pair>>>,
+    deposit_addresses: Arc<DepositAddresses<T::Pair>>,
+    tracker: Tracker<T>,
+    requests: Requests,
+    nonces: NonceManager<T>,
+    outbox: Outbox<T>,
+    indexer: Indexer<T>,
+    webhooks: Webhooks,
+    subscriptions: Subscriptions<T>,
+    era_period: u64,
+    tip: <T as Balances>::Balance,
+    max_tip: <T as Balances>::Balance,
+    denomination: Denomination,
+}
+
+impl<T: Exchange> Clone for RpcImpl<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            node: self.node.clone(),
+            db: self.db.clone(),
+            keystore: self.keystore.clone(),
+            deposit_addresses: self.deposit_addresses.clone(),
+            tracker: self.tracker.clone(),
+            requests: self.requests.clone(),
+            nonces: self.nonces.clone(),
+            outbox: self.outbox.clone(),
+            indexer: self.indexer.clone(),
+            webhooks: self.webhooks.clone(),
+            subscriptions: self.subscriptions.clone(),
+            era_period: self.era_period,
+            tip: self.tip,
+            max_tip: self.max_tip,
+            denomination: self.denomination.clone(),
+        }
+    }
 }
 
 impl<T: Exchange> RpcImpl<T>
 where
-    <T as System>::AccountId: std::hash::Hash,
+    <T as System>::AccountId: std::hash::Hash + Ss58Codec,
 {
     /// Creates a new `RpcImpl`.
-    pub fn new(client: Client<T>) -> Self {
-        Self { client, nonces: Default::default() }
+    pub fn new(
+        client: Client<T>,
+        node: Node<T>,
+        keystore: Keystore<T::Pair>,
+        db: Db,
+        config: Config<T>,
+    ) -> Result<Self, Error> {
+        if config.tip > config.max_tip {
+            return Err(Error::TipTooHigh)
+        }
+        let deposit_addresses = DepositAddresses::new(&db, config.deposit_master)?;
+        let outbox = Outbox::new(&db)?;
+        let webhooks = Webhooks::new(&db, config.webhooks)?;
+        let tracker = Tracker::new(
+            client.clone(),
+            Metadata::new(node.clone()),
+            outbox.clone(),
+            webhooks.clone(),
+        );
+        let indexer = Indexer::new(
+            client.clone(),
+            &db,
+            config.denomination.clone(),
+            config.credit_policy,
+            webhooks.clone(),
+        )?;
+        let subscriptions = Subscriptions::new(
+            client.clone(),
+            indexer.clone(),
+            config.denomination.clone(),
+            config.max_subscriptions,
+            config.max_balance_subscriptions,
+        );
+        Ok(Self {
+            indexer,
+            webhooks,
+            subscriptions,
+            nonces: NonceManager::new(client.clone(), tracker.clone()),
+            tracker,
+            outbox,
+            era_period: config.era_period,
+            tip: config.tip,
+            max_tip: config.max_tip,
+            denomination: config.denomination,
+            requests: Requests::new(&db)?,
+            db,
+            client,
+            node,
+            keystore: Arc::new(RwLock::new(keystore)),
+            deposit_addresses: Arc::new(deposit_addresses),
+        })
+    }
+
+    /// Returns the future spawning the background tasks of the shim which
+    /// follow the chain.
+    ///
+    /// Every task is spawned on its own, so a task which stops doesn't stop
+    /// the others.
+    pub fn background(&self) -> impl Future<Item = (), Error = ()> {
+        let tracker = self.tracker.clone().run();
+        let indexer = self.indexer.clone().run();
+        let webhooks = self.webhooks.clone().run();
+        let subscriptions = self.subscriptions.clone().run();
+        future::lazy(move || {
+            tokio::spawn(tracker);
+            tokio::spawn(indexer);
+            tokio::spawn(webhooks);
+            tokio::spawn(subscriptions);
+            Ok(())
+        })
+    }
+
+    /// Returns the balance and deposit subscriptions served over WebSocket.
+    pub fn subscriptions(&self) -> Subscriptions<T> {
+        self.subscriptions.clone()
+    }
+
+    /// Resolves the withdrawals left pending by a previous run.
+    ///
+    /// Withdrawals included in a canonical block are tracked until they are
+    /// finalized. Withdrawals whose nonce the chain already used are searched
+    /// for in the blocks since their submission and marked as dropped if they
+    /// are not found. Withdrawals whose era ended are left to the tracker,
+    /// which resolves them once the end of their era is finalized. All other
+    /// withdrawals are rebroadcast.
+    ///
+    /// Must complete before the rpc server is started.
+    pub fn recover(&self) -> impl Future<Item = (), Error = Error> {
+        let pending = match self.outbox.pending() {
+            Ok(pending) => pending,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        log::info!("recovering {} pending withdrawals", pending.len());
+        let rpc = self.clone();
+        let recover = self
+            .client
+            .header::<T::Hash>(None)
+            .map_err(Error::from)
+            .and_then(move |header| {
+                if let Some(header) = header {
+                    rpc.tracker.set_best_number(*header.number());
+                }
+                stream::iter_ok(pending).for_each(move |(hash, withdrawal)| {
+                    rpc.recover_withdrawal(hash, withdrawal)
+                })
+            });
+        Either::B(recover)
+    }
+
+    fn recover_withdrawal(
+        &self,
+        hash: T::Hash,
+        withdrawal: Withdrawal<T>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.tracker.restore(hash, &withdrawal);
+        let canonical = match &withdrawal.inclusion {
+            Some(inclusion) => {
+                let block_hash = inclusion.block_hash;
+                let canonical = self
+                    .client
+                    .block_hash(Some(inclusion.block_number.into()))
+                    .map(move |canonical| canonical == Some(block_hash))
+                    .map_err(Error::from);
+                Either::A(canonical)
+            }
+            None => Either::B(future::ok(false)),
+        };
+        let rpc = self.clone();
+        canonical.and_then(move |canonical| {
+            if canonical {
+                return Either::A(future::ok(()))
+            }
+            if withdrawal.inclusion.is_some() {
+                rpc.tracker.retracted(&hash);
+            }
+            let resolve = rpc
+                .client
+                .account_nonce(withdrawal.signer.clone())
+                .map_err(Error::from)
+                .and_then(move |account_nonce| {
+                    let best: u64 = rpc.tracker.best_number().saturated_into();
+                    let expired = withdrawal
+                        .status
+                        .death
+                        .map(|death| death <= best)
+                        .unwrap_or(false);
+                    if withdrawal.nonce < account_nonce {
+                        let tracker = rpc.tracker.clone();
+                        let scan = tracker.scan(hash, withdrawal.submitted_at).map(
+                            move |found| {
+                                match found {
+                                    Some((inclusion, outcome)) => {
+                                        tracker.included(&hash, inclusion, outcome)
+                                    }
+                                    None => tracker.dropped(&hash),
+                                }
+                            },
+                        );
+                        Either::A(scan)
+                    } else if expired {
+                        Either::B(future::ok(()))
+                    } else {
+                        Either::B(rpc.rebroadcast(hash, withdrawal))
+                    }
+                });
+            Either::B(resolve)
+        })
+    }
+
+    /// Submits the stored extrinsic of `withdrawal` again.
+    fn rebroadcast(
+        &self,
+        hash: T::Hash,
+        withdrawal: Withdrawal<T>,
+    ) -> impl Future<Item = (), Error = Error> {
+        log::info!("rebroadcasting {:?}", hash);
+        let client = self.client.clone();
+        let tracker = self.tracker.clone();
+        let nonce = withdrawal.nonce;
+        let extrinsic = extrinsic::Encoded(withdrawal.extrinsic);
+        self.nonces.reserve(withdrawal.signer).and_then(move |mut guard| {
+            client.submit_extrinsic(extrinsic).then(move |submitted| {
+                match submitted.map_err(Error::from) {
+                    // The pool still holding the extrinsic is fine.
+                    Ok(_) | Err(Error::PoolRejected(PoolError::AlreadyImported)) => {
+                        tracker.broadcast(&hash)
+                    }
+                    Err(err) => {
+                        log::warn!("rebroadcast of {:?} failed: {:?}", hash, err);
+                        tracker.invalid(&hash);
+                    }
+                }
+                guard.record(nonce, hash);
+                Ok::<_, Error>(())
+            })
+        })
+    }
+
+    /// Returns the value of the constant `name` of the module `module` from
+    /// the metadata of the node.
+    fn constant<V: Decode>(&self, module: &str, name: &str) -> Result<V, Error> {
+        let value = self
+            .client
+            .metadata()
+            .module(module)
+            .and_then(|module| module.constant(name)?.value())
+            .map_err(substrate_subxt::Error::from)?;
+        Ok(value)
+    }
+
+    /// Returns the hash and number of the block `at`, or of the best block.
+    fn block(
+        &self,
+        at: Option<At>,
+    ) -> impl Future<Item = (T::Hash, T::BlockNumber), Error = Error> {
+        let header = match at {
+            None => Either::A(self.client.header::<T::Hash>(None)),
+            Some(At::Hash(hash)) => {
+                let hash: T::Hash = match offline::from_hex(&hash) {
+                    Ok(hash) => hash,
+                    Err(_) => return Either::A(future::err(Error::InvalidHash)),
+                };
+                Either::A(self.client.header(Some(hash)))
+            }
+            Some(At::Number(number)) => {
+                let client = self.client.clone();
+                let number: T::BlockNumber = number.saturated_into();
+                let header = self
+                    .client
+                    .block_hash(Some(number.into()))
+                    .and_then(move |hash| {
+                        match hash {
+                            Some(hash) => Either::A(client.header(Some(hash))),
+                            None => Either::B(future::ok(None)),
+                        }
+                    });
+                Either::B(header)
+            }
+        };
+        let block = header.map_err(Error::from).and_then(|header| {
+            let header: T::Header = header.ok_or(Error::UnknownBlock)?;
+            Ok((header.hash(), *header.number()))
+        });
+        Either::B(block)
+    }
+
+    /// Returns the base fee and the fee per byte of an extrinsic.
+    fn length_fees(&self) -> Result<(T::Balance, T::Balance), Error> {
+        let base_fee = self.constant("Balances", "TransactionBaseFee")?;
+        let byte_fee = self.constant("Balances", "TransactionByteFee")?;
+        Ok((base_fee, byte_fee))
+    }
+
+    /// Returns the weight of the encoded `extrinsic` dispatching `call` and
+    /// the fee charged for it.
+    ///
+    /// The node reports the weight and fee of the runtime it runs. Nodes
+    /// without the payment rpc fall back to the compiled runtime. The weight of
+    /// a transfer doesn't depend on its amount, so the result applies to any
+    /// amount.
+    fn weight_fee(
+        &self,
+        call: &T::Call,
+        extrinsic: &[u8],
+    ) -> impl Future<Item = (u32, T::Balance), Error = Error> {
+        let (base_fee, byte_fee) = match self.length_fees() {
+            Ok(fees) => fees,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        let length_fee = byte_fee.saturating_mul((extrinsic.len() as u64).saturated_into());
+        let weight = T::call_weight(call);
+        let weight_fee = self.node.query_info(extrinsic).map(move |info| {
+            match info {
+                Some(info) => {
+                    let partial_fee: T::Balance = info.partial_fee.saturated_into();
+                    let weight_fee = partial_fee
+                        .saturating_sub(base_fee)
+                        .saturating_sub(length_fee);
+                    (info.weight, weight_fee)
+                }
+                None => (weight, T::weight_to_fee(weight)),
+            }
+        });
+        Either::B(weight_fee)
+    }
+
+    /// Returns the total fee of an extrinsic with the encoded length `length`
+    /// paying `tip` and the weight fee `weight_fee`, and its components.
+    fn fee(
+        &self,
+        (weight, weight_fee): (u32, T::Balance),
+        length: usize,
+        tip: T::Balance,
+    ) -> Result<(T::Balance, FeeEstimate), Error> {
+        let (base_fee, byte_fee) = self.length_fees()?;
+        let length_fee = byte_fee.saturating_mul((length as u64).saturated_into());
+        let total = base_fee
+            .saturating_add(length_fee)
+            .saturating_add(weight_fee)
+            .saturating_add(tip);
+        let estimate = FeeEstimate {
+            base_fee: self.denomination.amount(base_fee),
+            length_fee: self.denomination.amount(length_fee),
+            weight_fee: self.denomination.amount(weight_fee),
+            tip: self.denomination.amount(tip),
+            total: self.denomination.amount(total),
+            length: length as u32,
+            weight,
+        };
+        Ok((total, estimate))
+    }
+
+    /// Checks a transfer of `amount` paying `fee` against the existential
+    /// deposit.
+    ///
+    /// `from_balance` and `to_balance` are the free balances of the sender and
+    /// the recipient. Transfers leaving the sender below the existential
+    /// deposit are rejected if `keep_alive` is set, as reaping the account
+    /// resets its nonce. Transfers creating the recipient with less than the
+    /// existential deposit are always rejected, as the chain would refuse them.
+    fn check_existential_deposit(
+        &self,
+        from_balance: T::Balance,
+        to_balance: T::Balance,
+        amount: T::Balance,
+        fee: T::Balance,
+        keep_alive: bool,
+    ) -> Result<(), Error> {
+        let existential_deposit: T::Balance =
+            self.constant("Balances", "ExistentialDeposit")?;
+        let remaining = from_balance.saturating_sub(amount.saturating_add(fee));
+        if keep_alive && remaining < existential_deposit {
+            return Err(Error::WouldReapSender)
+        }
+        if to_balance.is_zero() && amount < existential_deposit {
+            return Err(Error::BelowExistentialDeposit)
+        }
+        Ok(())
+    }
+
+    /// Returns the amount a sweep paying `fee` sends.
+    ///
+    /// `free` and `transferable` are the free and transferable balances of the
+    /// sender. If `keep_alive` is set the existential deposit is left behind,
+    /// otherwise the account may be reaped.
+    fn sweep_amount(
+        &self,
+        free: T::Balance,
+        transferable: T::Balance,
+        fee: T::Balance,
+        keep_alive: bool,
+    ) -> Result<T::Balance, Error> {
+        let existential_deposit: T::Balance = if keep_alive {
+            self.constant("Balances", "ExistentialDeposit")?
+        } else {
+            Zero::zero()
+        };
+        let available = std::cmp::min(transferable, free.saturating_sub(existential_deposit));
+        if available <= fee {
+            return Err(Error::NothingToSweep)
+        }
+        Ok(available - fee)
+    }
+
+    /// Returns the tip of a transfer, checking it against the ceiling.
+    fn tip(&self, tip: Option<&str>) -> Result<T::Balance, Error> {
+        let tip = match tip {
+            Some(tip) => self.denomination.balance(tip)?,
+            None => self.tip,
+        };
+        if tip > self.max_tip {
+            return Err(Error::TipTooHigh)
+        }
+        Ok(tip)
+    }
+
+    /// Returns the era of a new transfer paying `tip`.
+    ///
+    /// Mortal eras are anchored at the finalized head, so the block the era
+    /// starts at can't be retracted.
+    fn signing(&self, tip: T::Balance) -> impl Future<Item = Signing<T>, Error = Error> {
+        let era_period = self.era_period;
+        let genesis_hash = self.client.genesis().clone();
+        let client = self.client.clone();
+        let finalized = {
+            let client = self.client.clone();
+            self.node
+                .finalized_head()
+                .and_then(move |hash| client.header(Some(hash)).map_err(Error::from))
+                .and_then(|header| {
+                    let header: T::Header = header.ok_or(Error::UnknownBlock)?;
+                    Ok(*header.number())
+                })
+        };
+        finalized.and_then(move |current| {
+            if era_period == 0 {
+                let signing = Signing {
+                    era: Era::Immortal,
+                    era_hash: genesis_hash,
+                    tip,
+                    mortality: None,
+                    current,
+                };
+                return Either::A(future::ok(signing))
+            }
+            let number: u64 = current.saturated_into();
+            let era = Era::mortal(era_period, number);
+            let mortality = Mortality::new(era, number);
+            let birth: T::BlockNumber = era.birth(number).saturated_into();
+            let signing = client
+                .block_hash(Some(birth.into()))
+                .map_err(Error::from)
+                .and_then(move |era_hash| {
+                    Ok(Signing {
+                        era,
+                        era_hash: era_hash.ok_or(Error::UnknownBlock)?,
+                        tip,
+                        mortality,
+                        current,
+                    })
+                });
+            Either::B(signing)
+        })
+    }
+
+    /// Returns the era and tip of an extrinsic signed for the era `era`
+    /// starting at the block `era_hash`.
+    ///
+    /// Immortal extrinsics are looked for from the best block on.
+    fn signing_of(
+        &self,
+        era: Era,
+        era_hash: T::Hash,
+        tip: T::Balance,
+    ) -> impl Future<Item = Signing<T>, Error = Error> {
+        let anchor = match era {
+            Era::Immortal => None,
+            Era::Mortal(..) => Some(era_hash),
+        };
+        self.client.header::<T::Hash>(anchor).map_err(Error::from).and_then(move |header| {
+            let header: T::Header = header.ok_or(Error::UnknownBlock)?;
+            let current = *header.number();
+            Ok(Signing {
+                era,
+                era_hash,
+                tip,
+                mortality: Mortality::new(era, current.saturated_into()),
+                current,
+            })
+        })
+    }
+
+    /// Returns the outbox record of the signed `extrinsic`.
+    fn withdrawal<E: Encode>(
+        &self,
+        extrinsic: &E,
+        signer: T::AccountId,
+        nonce: T::Index,
+        call: T::Call,
+        signing: Signing<T>,
+        request_id: Option<String>,
+    ) -> Withdrawal<T> {
+        let hash = extrinsic::hash::<T, _>(extrinsic);
+        let death = signing.mortality.map(|mortality| mortality.death);
+        Withdrawal {
+            request_id,
+            signer,
+            nonce,
+            call,
+            era: signing.era,
+            era_hash: signing.era_hash,
+            tip: signing.tip,
+            extrinsic: extrinsic.encode(),
+            submitted_at: signing.earliest(),
+            inclusion: None,
+            status: Tracker::<T>::new_status(&hash, death),
+        }
+    }
+
+    /// Returns the result of the transfer recorded in the outbox with the
+    /// extrinsic `hash`.
+    fn transfer_result(&self, hash: &str) -> impl Future<Item = TransferResult, Error = Error> {
+        let recorded = offline::from_hex(hash)
+            .map_err(|_| Error::CorruptedDatabase)
+            .and_then(|hash| {
+                let withdrawal = self.outbox.get(&hash)?.ok_or(Error::UnknownTransaction)?;
+                Ok((hash, withdrawal))
+            });
+        let (hash, withdrawal) = match recorded {
+            Ok(recorded) => recorded,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        let rpc = self.clone();
+        let weight_fee = self.weight_fee(&withdrawal.call, &withdrawal.extrinsic);
+        let result = weight_fee.and_then(move |weight_fee| {
+            let (fee, _) = rpc.fee(weight_fee, withdrawal.extrinsic.len(), withdrawal.tip)?;
+            let amount = T::transfer_amount(&withdrawal.call).unwrap_or_else(Zero::zero);
+            let status = rpc.tracker.status(&hash).unwrap_or(withdrawal.status);
+            Ok(TransferResult {
+                hash: status.hash,
+                nonce: withdrawal.nonce.saturated_into(),
+                amount: rpc.denomination.amount(amount),
+                fee: rpc.denomination.amount(fee),
+                tip: rpc.denomination.amount(withdrawal.tip),
+                state: status.state,
+                era: Mortality::new(withdrawal.era, withdrawal.submitted_at.saturated_into()),
+                block: status.block,
+                outcome: status.outcome,
+            })
+        });
+        Either::B(result)
+    }
+
+    /// Returns the result of a request whose extrinsic `hashes[0]` was
+    /// replaced by the others.
+    ///
+    /// Reports the included extrinsic if there is one, and otherwise the
+    /// latest extrinsic waiting in the pool or the latest extrinsic.
+    fn request_result(
+        &self,
+        hashes: &[String],
+    ) -> impl Future<Item = TransferResult, Error = Error> {
+        let results: Vec<_> =
+            hashes.iter().rev().map(|hash| self.transfer_result(hash)).collect();
+        future::join_all(results).and_then(|results| {
+            let rank = |result: &TransferResult| {
+                match result.state {
+                    TxState::InBlock | TxState::Finalized => 2,
+                    state if state.is_in_pool() => 1,
+                    _ => 0,
+                }
+            };
+            let mut best: Option<TransferResult> = None;
+            for result in results {
+                if best.as_ref().map(|best| rank(&result) > rank(best)).unwrap_or(true) {
+                    best = Some(result);
+                }
+            }
+            best.ok_or(Error::CorruptedDatabase)
+        })
+    }
+
+    /// Records `withdrawal` in the outbox together with the extrinsic of its
+    /// request, submits the extrinsic to the transaction pool and starts
+    /// tracking it.
+    ///
+    /// An extrinsic the pool rejected is marked invalid. On other errors it
+    /// stays broadcast until the tracker finds out whether it reached the
+    /// pool.
+    fn submit(&self, withdrawal: Withdrawal<T>) -> impl Future<Item = T::Hash, Error = Error> {
+        let extrinsic = extrinsic::Encoded(withdrawal.extrinsic.clone());
+        let hash = extrinsic::hash::<T, _>(&extrinsic);
+        log::info!("{:?}", hash);
+        let mut batch = Batch::default();
+        self.outbox.insert(&mut batch, &hash, &withdrawal);
+        let recorded = match &withdrawal.request_id {
+            Some(id) => self.requests.signed(&mut batch, id, &format!("{:?}", hash)),
+            None => Ok(()),
+        };
+        if let Err(err) = recorded.and_then(|_| self.db.apply(batch)) {
+            return Either::A(future::err(err))
+        }
+        let tracker = self.tracker.clone();
+        tracker.submitted(
+            hash,
+            withdrawal.signer,
+            withdrawal.nonce,
+            withdrawal.submitted_at,
+            withdrawal.status.death,
+        );
+        let submit = self
+            .client
+            .submit_extrinsic(extrinsic)
+            .map({
+                let tracker = tracker.clone();
+                move |_| {
+                    tracker.broadcast(&hash);
+                    hash
+                }
+            })
+            .map_err(move |err| {
+                let err = Error::from(err);
+                if err.is_rejection() {
+                    tracker.invalid(&hash);
+                } else {
+                    // The extrinsic may be in the pool, the tracker finds out.
+                    tracker.broadcast(&hash);
+                }
+                err
+            });
+        Either::B(submit)
+    }
+
+    /// Waits until the extrinsic `hash` reached the point given by `wait`.
+    fn wait(
+        &self,
+        hash: &T::Hash,
+        wait: Wait,
+    ) -> impl Future<Item = TransactionStatus, Error = Error> {
+        self.tracker.wait(hash, wait).and_then(|status| {
+            match (status.state, status.outcome.clone()) {
+                (TxState::Dropped, _) => Err(Error::Dropped),
+                (TxState::Expired, _) => Err(Error::Expired),
+                (_, Some(Outcome::Failed(failure))) => {
+                    Err(Error::DispatchFailed {
+                        hash: status.hash,
+                        failure,
+                    })
+                }
+                _ => Ok(status),
+            }
+        })
     }
 }
 
+impl<T: Exchange> RpcImpl<T>
+where
+    <T as System>::AccountId: std::hash::Hash + Ss58Codec,
+    <T::Pair as Pair>::Public: Into<<T as System>::AccountId>
+        + Into<<<T as System>::Lookup as StaticLookup>::Source>,
+{
+    /// Signs and submits a transfer of `amount` from `pair` to `to`, and waits
+    /// for it as requested by `options`.
+    ///
+    /// A transfer without an amount sweeps the account, sending its
+    /// transferable balance minus the fee.
+    fn transfer(
+        &self,
+        pair: T::Pair,
+        to: <T::Pair as Pair>::Public,
+        amount: Option<T::Balance>,
+        tip: T::Balance,
+        options: TransferOptions,
+    ) -> impl Future<Item = TransferResult, Error = Error> {
+        let account: T::AccountId = pair.public().into();
+        let dest: T::AccountId = to.clone().into();
+        let request_id = options.request_id.clone();
+        if let Some(id) = &request_id {
+            // A default tip may change between retries, an explicit one not.
+            let explicit_tip = options.tip.as_ref().map(|_| tip);
+            let fingerprint = Requests::fingerprint(&(
+                &account,
+                &dest,
+                amount,
+                explicit_tip,
+                options.keep_alive,
+            ));
+            match self.requests.begin(id, fingerprint) {
+                Ok(Some(hashes)) => {
+                    log::info!("repeated request {}", id);
+                    return Either::A(Either::A(self.request_result(&hashes)))
+                }
+                Ok(None) => {}
+                Err(err) => return Either::A(Either::B(future::err(err))),
+            }
+        }
+        let genesis_hash = self.client.genesis().clone();
+        let rpc = self.clone();
+        let nonces = self.nonces.clone();
+        let keep_alive = options.keep_alive;
+        let client = self.client.clone();
+        let sender = account.clone();
+        let balances = self
+            .block(None)
+            .and_then(move |(block_hash, block_number)| {
+                balance::transferable(&client, &sender, block_hash, block_number)
+            })
+            .join(self.client.free_balance(dest).map_err(Error::from));
+        let reserve = self.signing(tip).join(balances).and_then(move |(signing, balances)| {
+            nonces
+                .reserve(account.clone())
+                .map(move |guard| (guard, account, signing, balances))
+        });
+        let submit = reserve.and_then(move |(guard, account, signing, balances)| {
+            let ((from_balance, transferable), to_balance) = balances;
+            let call = T::transfer_call(to.clone().into(), amount.unwrap_or(transferable));
+            let xt = extrinsic::sign::<T>(
+                &pair,
+                call.clone(),
+                guard.nonce(),
+                signing.era,
+                signing.tip,
+                &genesis_hash,
+                &signing.era_hash,
+            );
+            let weight_fee = match xt {
+                Ok(xt) => Either::A(rpc.weight_fee(&call, &xt.encode())),
+                Err(err) => Either::B(future::err(err)),
+            };
+            weight_fee.and_then(move |weight_fee| {
+                future::loop_fn((guard, false), move |(guard, retried)| {
+                    let nonce = guard.nonce();
+                    let sign = |amount| {
+                        let call = T::transfer_call(to.clone().into(), amount);
+                        let xt = extrinsic::sign::<T>(
+                            &pair,
+                            call.clone(),
+                            nonce,
+                            signing.era,
+                            signing.tip,
+                            &genesis_hash,
+                            &signing.era_hash,
+                        )?;
+                        Ok((call, xt))
+                    };
+                    let amount = match amount {
+                        Some(amount) => Ok(amount),
+                        None => {
+                            // A smaller amount doesn't encode longer, so the fee
+                            // of sending everything is an upper bound.
+                            sign(transferable).and_then(|(_, xt)| {
+                                let length = xt.encode().len();
+                                let (fee, _) = rpc.fee(weight_fee, length, signing.tip)?;
+                                rpc.sweep_amount(from_balance, transferable, fee, keep_alive)
+                            })
+                        }
+                    };
+                    let amount = match amount {
+                        Ok(amount) => amount,
+                        Err(err) => return Either::A(future::err(err)),
+                    };
+                    let (call, xt) = match sign(amount) {
+                        Ok(signed) => signed,
+                        Err(err) => return Either::A(future::err(err)),
+                    };
+                    let length = xt.encode().len();
+                    let fee = rpc.fee(weight_fee, length, signing.tip).and_then(|(fee, _)| {
+                        let required = amount.saturating_add(fee);
+                        if required > transferable {
+                            return Err(Error::InsufficientFunds {
+                                required: Some(required.saturated_into()),
+                                available: Some(transferable.saturated_into()),
+                            })
+                        }
+                        rpc.check_existential_deposit(
+                            from_balance,
+                            to_balance,
+                            amount,
+                            fee,
+                            keep_alive,
+                        )?;
+                        Ok(fee)
+                    });
+                    let fee = match fee {
+                        Ok(fee) => fee,
+                        Err(err) => return Either::A(future::err(err)),
+                    };
+                    let hash = extrinsic::hash::<T, _>(&xt);
+                    let result = TransferResult {
+                        hash: format!("{:?}", hash),
+                        nonce: nonce.saturated_into(),
+                        amount: rpc.denomination.amount(amount),
+                        fee: rpc.denomination.amount(fee),
+                        tip: rpc.denomination.amount(signing.tip),
+                        state: TxState::Ready,
+                        era: signing.mortality.clone(),
+                        block: None,
+                        outcome: None,
+                    };
+                    let nonces = rpc.nonces.clone();
+                    let withdrawal = rpc.withdrawal(
+                        &xt,
+                        account.clone(),
+                        nonce,
+                        call,
+                        signing.clone(),
+                        request_id.clone(),
+                    );
+                    let submit = rpc.submit(withdrawal);
+                    let submit = submit.then(move |submitted| {
+                        match submitted {
+                            Ok(hash) => {
+                                guard.submitted(hash);
+                                Either::A(future::ok(Loop::Break((hash, result))))
+                            }
+                            Err(ref err) if !retried && is_nonce_rejection(err) => {
+                                log::warn!("nonce {:?} rejected, resyncing", nonce);
+                                let resync = nonces.resync(guard);
+                                Either::B(resync.map(|guard| Loop::Continue((guard, true))))
+                            }
+                            Err(err) => {
+                                if !err.is_rejection() {
+                                    // The nonce stays reserved while the
+                                    // extrinsic may be in the pool.
+                                    guard.submitted(hash);
+                                }
+                                Either::A(future::err(err))
+                            }
+                        }
+                    });
+                    Either::B(submit)
+                })
+            })
+        });
+        // Release the request id unless the extrinsic may be in the pool, a
+        // retry then returns its status.
+        let requests = self.requests.clone();
+        let request_id = options.request_id.clone();
+        let rpc = self.clone();
+        let transfer = submit
+            .then(move |submitted| {
+                if let (Err(err), Some(id)) = (&submitted, &request_id) {
+                    requests.release(id, err.is_rejection())?;
+                }
+                submitted
+            })
+            .and_then(move |(hash, mut result)| {
+                rpc.wait(&hash, options.wait).map(move |status| {
+                    result.update(status);
+                    result
+                })
+            });
+        Either::B(transfer)
+    }
+}
 
 impl<T: Exchange> Rpc<T> for RpcImpl<T>
 where
-    <T as System>::AccountId: std::hash::Hash,
+    <T as System>::AccountId: std::hash::Hash + Ss58Codec,
     <T::Pair as Pair>::Public:
         Ss58Codec
+            + Derive
             + Into<<T as System>::AccountId>
             + Into<<<T as System>::Lookup as StaticLookup>::Source>,
-    <T::Pair as Pair>::Signature: Codec,
-    <T as Balances>::Balance: std::fmt::Display + std::str::FromStr,
-    <<T as Balances>::Balance as std::str::FromStr>::Err: std::fmt::Debug,
+    <T::Pair as Pair>::Public: Codec,
+    <T::Pair as Pair>::Signature: Codec + Default,
+    <T as Balances>::Balance: std::fmt::Display,
 {
     fn account_balance(
         &self,
         of: String,
-    ) -> Box<dyn Future<Item = String, Error = RpcError> + Send> {
+    ) -> Box<dyn Future<Item = Amount, Error = RpcError> + Send> {
         let params = || {
             let public = <T::Pair as Pair>::Public::from_string(&of)
               .map_err(|_| Error::InvalidSS58)?;
@@ -112,48 +1680,475 @@ where
             Ok(params) => params,
             Err(err) => return Box::new(future::err(err.into())),
         };
+        let denomination = self.denomination.clone();
         let free_balance = self.client.free_balance(public.into())
-            .map(|balance| format!("{}", balance))
-            .map_err(|e| {
-                log::error!("{:?}", e);
-                RpcError::internal_error()
-            });
+            .map(move |balance| denomination.amount(balance))
+            .map_err(|err| Error::from(err).into());
         Box::new(free_balance)
     }
 
+    fn account_balance_details(
+        &self,
+        of: String,
+        at: Option<At>,
+    ) -> Box<dyn Future<Item = AccountBalance, Error = RpcError> + Send> {
+        let account: T::AccountId = match <T::Pair as Pair>::Public::from_string(&of) {
+            Ok(public) => public.into(),
+            Err(_) => return Box::new(future::err(Error::InvalidSS58.into())),
+        };
+        let client = self.client.clone();
+        let denomination = self.denomination.clone();
+        let details = self
+            .block(at)
+            .and_then(move |(block_hash, block_number)| {
+                balance::account_balance(
+                    &client,
+                    &denomination,
+                    &account,
+                    block_hash,
+                    block_number,
+                )
+            })
+            .map_err(Into::into);
+        Box::new(details)
+    }
+
+    fn accounts_balances(
+        &self,
+        addresses: Vec<String>,
+        at: Option<At>,
+    ) -> Box<dyn Future<Item = AccountsBalances, Error = RpcError> + Send> {
+        if addresses.len() > MAX_BATCH_ADDRESSES {
+            return Box::new(future::err(Error::TooManyAddresses.into()))
+        }
+        let parsed: Vec<Option<T::AccountId>> = addresses
+            .iter()
+            .map(|address| {
+                <T::Pair as Pair>::Public::from_string(address)
+                    .ok()
+                    .map(Into::into)
+            })
+            .collect();
+        let accounts: Vec<T::AccountId> = parsed.iter().flatten().cloned().collect();
+        let node = self.node.clone();
+        let denomination = self.denomination.clone();
+        let balances = self
+            .block(at)
+            .and_then(move |(block_hash, block_number)| {
+                balance::accounts_balances(
+                    &node,
+                    &denomination,
+                    &accounts,
+                    block_hash,
+                    block_number,
+                )
+                .map(move |balances| {
+                    let mut balances = balances.into_iter();
+                    let balances = addresses
+                        .into_iter()
+                        .zip(parsed)
+                        .map(|(address, account)| {
+                            let balance = match account {
+                                Some(_) => balances.next().unwrap_or(Err(Error::InvalidResponse)),
+                                None => Err(Error::InvalidSS58),
+                            };
+                            match balance {
+                                Ok(balance) => {
+                                    BatchEntry {
+                                        address,
+                                        balance: Some(balance),
+                                        error: None,
+                                    }
+                                }
+                                Err(err) => {
+                                    BatchEntry {
+                                        address,
+                                        balance: None,
+                                        error: Some(RpcError::from(err).message),
+                                    }
+                                }
+                            }
+                        })
+                        .collect();
+                    AccountsBalances {
+                        block_hash: format!("{:?}", block_hash),
+                        block_number: block_number.saturated_into(),
+                        balances,
+                    }
+                })
+            })
+            .map_err(Into::into);
+        Box::new(balances)
+    }
+
     fn transfer_balance(
         &self,
         from: String,
         to: String,
         amount: String,
-    ) -> Box<dyn Future<Item = (), Error = RpcError> + Send> {
+        options: Option<TransferOptions>,
+    ) -> Box<dyn Future<Item = TransferResult, Error = RpcError> + Send> {
         let params = || {
-            let pair = T::Pair::from_string(&from, None)
-              .map_err(|_| Error::InvalidSURI)?;
+            let pair = self.keystore.read().unwrap().get(&from)?.clone();
             let public = <T::Pair as Pair>::Public::from_string(&to)
             .map_err(|_| Error::InvalidSS58)?;
-            let balance = amount.parse().map_err(|_| Error::InvalidBalance)?;
-            let result: Result<_, Error> = Ok((pair, public, balance));
+            let balance = self.denomination.balance(&amount)?;
+            let tip = options.as_ref().and_then(|options| options.tip.as_ref());
+            let tip = self.tip(tip.map(String::as_str))?;
+            let result: Result<_, Error> = Ok((pair, public, balance, tip));
             result
         };
-        let (pair, public, balance) = match params() {
+        let (pair, public, balance, tip) = match params() {
             Ok(params) => params,
             Err(err) => return Box::new(future::err(err.into())),
         };
-        let nonce = self.nonces.lock().unwrap().get(&pair.public().into()).cloned();
-        let nonces = self.nonces.clone();
-        let transfer = self.client.xt(pair.clone(), nonce)
-            .and_then(move |mut xt| {
-                let fut = xt.transfer(public.into(), balance);
-                nonces.lock().unwrap()
-                    .insert(pair.public().into(), xt.nonce());
-                fut
-            })
-            .map(|hash| log::info!("{:?}", hash))
-            .map_err(|e| {
-                log::error!("{:?}", e);
-                RpcError::internal_error()
-            });
+        let options = options.unwrap_or_default();
+        let transfer = self
+            .transfer(pair, public, Some(balance), tip, options)
+            .map_err(Into::into);
         Box::new(transfer)
     }
+
+    fn transfer_sweep(
+        &self,
+        from: String,
+        to: String,
+        options: Option<TransferOptions>,
+    ) -> Box<dyn Future<Item = TransferResult, Error = RpcError> + Send> {
+        let params = || {
+            let pair = self.keystore.read().unwrap().get(&from)?.clone();
+            let public = <T::Pair as Pair>::Public::from_string(&to)
+                .map_err(|_| Error::InvalidSS58)?;
+            let tip = options.as_ref().and_then(|options| options.tip.as_ref());
+            let tip = self.tip(tip.map(String::as_str))?;
+            let result: Result<_, Error> = Ok((pair, public, tip));
+            result
+        };
+        let (pair, public, tip) = match params() {
+            Ok(params) => params,
+            Err(err) => return Box::new(future::err(err.into())),
+        };
+        let options = options.unwrap_or_default();
+        let sweep = self.transfer(pair, public, None, tip, options).map_err(Into::into);
+        Box::new(sweep)
+    }
+
+    fn estimate_transfer_fee(
+        &self,
+        from: String,
+        to: String,
+        amount: String,
+        options: Option<TransferOptions>,
+    ) -> Box<dyn Future<Item = FeeEstimate, Error = RpcError> + Send> {
+        let params = || {
+            let signer = match self.keystore.read().unwrap().get(&from) {
+                Ok(pair) => pair.public(),
+                Err(_) => {
+                    <T::Pair as Pair>::Public::from_string(&from)
+                        .map_err(|_| Error::InvalidSS58)?
+                }
+            };
+            let public = <T::Pair as Pair>::Public::from_string(&to)
+                .map_err(|_| Error::InvalidSS58)?;
+            let balance = self.denomination.balance(&amount)?;
+            let tip = options.as_ref().and_then(|options| options.tip.as_ref());
+            let tip = self.tip(tip.map(String::as_str))?;
+            let result: Result<_, Error> = Ok((signer, public, balance, tip));
+            result
+        };
+        let (signer, public, balance, tip) = match params() {
+            Ok(params) => params,
+            Err(err) => return Box::new(future::err(err.into())),
+        };
+        let call = T::transfer_call(public.into(), balance);
+        let nonce = self.client.account_nonce(signer.clone().into()).map_err(Error::from);
+        let rpc = self.clone();
+        let estimate = self
+            .signing(tip)
+            .join(nonce)
+            .and_then(move |(signing, nonce)| {
+                // The signature doesn't change the length of the extrinsic.
+                let xt = extrinsic::signed::<T>(
+                    call.clone(),
+                    signer,
+                    Default::default(),
+                    T::signed_extra(
+                        nonce,
+                        signing.era,
+                        signing.tip,
+                        rpc.client.genesis().clone(),
+                        signing.era_hash,
+                    ),
+                );
+                let xt = xt.encode();
+                rpc.weight_fee(&call, &xt).and_then(move |weight_fee| {
+                    let (_, estimate) = rpc.fee(weight_fee, xt.len(), signing.tip)?;
+                    Ok(estimate)
+                })
+            })
+            .map_err(Into::into);
+        Box::new(estimate)
+    }
+
+    fn transfer_bump_tip(
+        &self,
+        hash: String,
+        tip: String,
+    ) -> Box<dyn Future<Item = TransferResult, Error = RpcError> + Send> {
+        let params = || {
+            let hash = offline::from_hex(&hash).map_err(|_| Error::InvalidHash)?;
+            let withdrawal = self.outbox.get(&hash)?.ok_or(Error::UnknownTransaction)?;
+            match self.tracker.status(&hash).map(|status| status.state) {
+                Some(state) if state.is_in_pool() => {}
+                _ => return Err(Error::NotPending),
+            }
+            let tip = self.tip(Some(&tip))?;
+            if tip <= withdrawal.tip {
+                return Err(Error::TipTooLow)
+            }
+            let keystore = self.keystore.read().unwrap();
+            let id = keystore
+                .accounts()
+                .find(|(_, public)| {
+                    let account: T::AccountId = public.clone().into();
+                    account == withdrawal.signer
+                })
+                .map(|(id, _)| id.to_string())
+                .ok_or(Error::UnknownAccount)?;
+            let pair = keystore.get(&id)?.clone();
+            Ok((withdrawal, pair, tip))
+        };
+        let (withdrawal, pair, tip) = match params() {
+            Ok(params) => params,
+            Err(err) => return Box::new(future::err(err.into())),
+        };
+        let genesis_hash = self.client.genesis().clone();
+        let nonces = self.nonces.clone();
+        let signer = withdrawal.signer.clone();
+        let rpc = self.clone();
+        let weight_fee = self.weight_fee(&withdrawal.call, &withdrawal.extrinsic);
+        let bump = self
+            .signing_of(withdrawal.era, withdrawal.era_hash, tip)
+            .join(weight_fee)
+            .and_then(move |(signing, weight_fee)| {
+                nonces.reserve(signer).map(move |guard| (guard, signing, weight_fee))
+            })
+            .and_then(move |(mut guard, signing, weight_fee)| {
+                let nonce = withdrawal.nonce;
+                let xt = extrinsic::sign::<T>(
+                    &pair,
+                    withdrawal.call.clone(),
+                    nonce,
+                    signing.era,
+                    signing.tip,
+                    &genesis_hash,
+                    &signing.era_hash,
+                );
+                let fee = xt.and_then(|xt| {
+                    let (fee, _) = rpc.fee(weight_fee, xt.encode().len(), signing.tip)?;
+                    Ok((xt, fee))
+                });
+                let (xt, fee) = match fee {
+                    Ok(fee) => fee,
+                    Err(err) => return Either::A(future::err(err)),
+                };
+                let amount = T::transfer_amount(&withdrawal.call).unwrap_or_else(Zero::zero);
+                let result = TransferResult {
+                    hash: format!("{:?}", extrinsic::hash::<T, _>(&xt)),
+                    nonce: nonce.saturated_into(),
+                    amount: rpc.denomination.amount(amount),
+                    fee: rpc.denomination.amount(fee),
+                    tip: rpc.denomination.amount(signing.tip),
+                    state: TxState::Ready,
+                    era: signing.mortality.clone(),
+                    block: None,
+                    outcome: None,
+                };
+                let replacement = rpc.withdrawal(
+                    &xt,
+                    withdrawal.signer,
+                    nonce,
+                    withdrawal.call,
+                    signing,
+                    withdrawal.request_id,
+                );
+                let submit = rpc.submit(replacement).map(move |hash| {
+                    log::info!("replaced with {:?} paying tip {}", hash, result.tip.formatted);
+                    guard.record(nonce, hash);
+                    result
+                });
+                Either::B(submit)
+            })
+            .map_err(Into::into);
+        Box::new(bump)
+    }
+
+    fn keystore_lock(&self) -> Result<(), RpcError> {
+        self.keystore.write().unwrap().lock();
+        log::info!("keystore locked");
+        Ok(())
+    }
+
+    fn account_new_deposit_address(
+        &self,
+        customer_id: String,
+    ) -> Result<DepositAddress, RpcError> {
+        let deposit = self.deposit_addresses.create(&customer_id)?;
+        log::info!("new deposit address {} for {}", deposit.address, customer_id);
+        let public = <T::Pair as Pair>::Public::from_string(&deposit.address)
+            .map_err(|_| Error::InvalidSS58)?;
+        self.indexer.watch(&public.into(), Some(customer_id))?;
+        Ok(deposit)
+    }
+
+    fn deposit_watch_address(
+        &self,
+        address: String,
+        label: Option<String>,
+    ) -> Result<WatchedAddress, RpcError> {
+        let public = <T::Pair as Pair>::Public::from_string(&address)
+            .map_err(|_| Error::InvalidSS58)?;
+        let watched = self.indexer.watch(&public.into(), label)?;
+        log::info!("watching {}", watched.address);
+        Ok(watched)
+    }
+
+    fn deposit_unwatch_address(&self, address: String) -> Result<bool, RpcError> {
+        let public = <T::Pair as Pair>::Public::from_string(&address)
+            .map_err(|_| Error::InvalidSS58)?;
+        let watched = self.indexer.unwatch(&public.into())?;
+        Ok(watched.is_some())
+    }
+
+    fn deposit_list(&self, address: Option<String>) -> Result<Vec<Deposit>, RpcError> {
+        let account: Option<T::AccountId> = match address {
+            Some(address) => {
+                let public = <T::Pair as Pair>::Public::from_string(&address)
+                    .map_err(|_| Error::InvalidSS58)?;
+                Some(public.into())
+            }
+            None => None,
+        };
+        Ok(self.indexer.deposits(account.as_ref())?)
+    }
+
+    fn deposit_credit_ready(&self, cursor: Option<u64>) -> Result<CreditReady, RpcError> {
+        Ok(self.indexer.credit_ready(cursor)?)
+    }
+
+    fn deposit_checkpoint(&self) -> Result<Option<Checkpoint>, RpcError> {
+        Ok(self.indexer.checkpoint()?)
+    }
+
+    fn deposit_backfill(
+        &self,
+        from: u64,
+        to: Option<u64>,
+    ) -> Box<dyn Future<Item = Backfill, Error = RpcError> + Send> {
+        let to = match to {
+            Some(to) => to,
+            None => {
+                match self.indexer.checkpoint() {
+                    Ok(checkpoint) => {
+                        checkpoint.map(|checkpoint| checkpoint.number).unwrap_or_default()
+                    }
+                    Err(err) => return Box::new(future::err(err.into())),
+                }
+            }
+        };
+        if from > to || to - from >= MAX_BACKFILL_BLOCKS {
+            return Box::new(future::err(Error::InvalidBackfillRange.into()))
+        }
+        Box::new(self.indexer.backfill(from, to).map_err(Into::into))
+    }
+
+    fn transfer_payload(
+        &self,
+        from: String,
+        to: String,
+        amount: String,
+    ) -> Box<dyn Future<Item = String, Error = RpcError> + Send> {
+        let params = || {
+            let signer = <T::Pair as Pair>::Public::from_string(&from)
+                .map_err(|_| Error::InvalidSS58)?;
+            let public = <T::Pair as Pair>::Public::from_string(&to)
+                .map_err(|_| Error::InvalidSS58)?;
+            let balance = self.denomination.balance(&amount)?;
+            let result: Result<_, Error> = Ok((signer, public, balance));
+            result
+        };
+        let (signer, public, balance) = match params() {
+            Ok(params) => params,
+            Err(err) => return Box::new(future::err(err.into())),
+        };
+        let genesis_hash = self.client.genesis().clone();
+        let nonces = self.nonces.clone();
+        let payload = self.signing(self.tip)
+            .and_then(move |signing| {
+                nonces.reserve(signer.clone().into()).map(move |guard| (guard, signing))
+            })
+            .map(move |(guard, signing)| {
+                let nonce = guard.nonce();
+                guard.offline(signing.mortality.as_ref().map(|mortality| mortality.death));
+                let transfer = UnsignedTransfer::<T> {
+                    version: offline::VERSION,
+                    signer,
+                    call: T::transfer_call(public.into(), balance),
+                    nonce,
+                    tip: signing.tip,
+                    era: signing.era,
+                    genesis_hash,
+                    era_hash: signing.era_hash,
+                };
+                offline::to_hex(&transfer)
+            })
+            .map_err(|err| Error::from(err).into());
+        Box::new(payload)
+    }
+
+    fn submit_signed_transfer(
+        &self,
+        transfer: String,
+    ) -> Box<dyn Future<Item = String, Error = RpcError> + Send> {
+        let params = || {
+            let signed = SignedTransfer::<T>::from_hex(&transfer)?;
+            if &signed.transfer.genesis_hash != self.client.genesis() {
+                return Err(Error::InvalidPayload)
+            }
+            if signed.transfer.tip > self.max_tip {
+                return Err(Error::TipTooHigh)
+            }
+            let signer: T::AccountId = signed.transfer.signer.clone().into();
+            let nonce = signed.transfer.nonce;
+            let call = signed.transfer.call.clone();
+            let era = (signed.transfer.era, signed.transfer.era_hash, signed.transfer.tip);
+            Ok((signed.into_extrinsic()?, call, signer, nonce, era))
+        };
+        let (extrinsic, call, signer, nonce, (era, era_hash, tip)) = match params() {
+            Ok(params) => params,
+            Err(err) => return Box::new(future::err(err.into())),
+        };
+        let rpc = self.clone();
+        let nonces = self.nonces.clone();
+        let submit = self
+            .signing_of(era, era_hash, tip)
+            .and_then(move |signing| {
+                nonces.reserve(signer.clone()).map(move |guard| (guard, signer, signing))
+            })
+            .and_then(move |(mut guard, signer, signing)| {
+                let withdrawal =
+                    rpc.withdrawal(&extrinsic, signer, nonce, call, signing, None);
+                rpc.submit(withdrawal).map(move |hash| {
+                    guard.record(nonce, hash);
+                    format!("{:?}", hash)
+                })
+            })
+            .map_err(Into::into);
+        Box::new(submit)
+    }
+
+    fn transaction_status(&self, hash: String) -> Result<TransactionStatus, RpcError> {
+        let hash = offline::from_hex(&hash).map_err(|_| Error::InvalidHash)?;
+        Ok(self.tracker.status(&hash).ok_or(Error::UnknownTransaction)?)
+    }
 }
@@ -3,13 +3,67 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-use jsonrpc_core::IoHandler;
+use jsonrpc_core::{
+    IoHandler,
+    MetaIoHandler,
+};
 use jsonrpc_http_server::ServerBuilder;
-use sr_primitives::generic::Era;
+use jsonrpc_pubsub::{
+    PubSubHandler,
+    Session,
+};
+use jsonrpc_ws_server::{
+    RequestContext,
+    ServerBuilder as WsServerBuilder,
+};
+use sr_primitives::{
+    generic::Era,
+    traits::{
+        Convert,
+        StaticLookup,
+    },
+    weights::GetDispatchInfo,
+};
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
+};
+use structopt::StructOpt;
 use substrate_exchange::{
+    offline::{
+        self,
+        UnsignedTransfer,
+    },
+    At,
+    CheckEra,
+    CheckGenesis,
+    Config,
+    Credit,
+    CreditPolicy,
+    Db,
+    Denomination,
+    DispatchFailure,
+    Error as ExchangeError,
     Exchange,
+    Keystore,
+    Meta,
+    Node,
+    Outcome,
+    PubSub,
     Rpc,
     RpcImpl,
+    TransferOptions,
+    TransferResult,
+    TxState,
+    Wait,
+    WebhookConfig,
+};
+use substrate_primitives::crypto::{
+    Pair,
+    Ss58Codec,
 };
 use substrate_subxt::{
     ClientBuilder,
@@ -19,6 +73,7 @@ use substrate_subxt::{
         system::System,
     },
 };
+use zeroize::Zeroizing;
 
 struct Runtime;
 
@@ -33,21 +88,17 @@ impl System for Runtime {
     type Event = <node_runtime::Runtime as srml_system::Trait>::Event;
 
     type SignedExtra = (
-        srml_system::CheckGenesis<node_runtime::Runtime>,
-        srml_system::CheckEra<node_runtime::Runtime>,
+        CheckGenesis<<Self as System>::Hash>,
+        CheckEra<<Self as System>::Hash>,
         srml_system::CheckNonce<node_runtime::Runtime>,
         srml_system::CheckWeight<node_runtime::Runtime>,
         srml_balances::TakeFees<node_runtime::Runtime>,
     );
 
+    /// Only used by subxt's own extrinsic builder, which the shim doesn't
+    /// use. The extensions don't know the genesis hash here.
     fn extra(nonce: <Self as System>::Index) -> Self::SignedExtra {
-        (
-            srml_system::CheckGenesis::new(),
-            srml_system::CheckEra::from(Era::Immortal),
-            srml_system::CheckNonce::from(nonce),
-            srml_system::CheckWeight::new(),
-            srml_balances::TakeFees::from(0),
-        )
+        Self::signed_extra(nonce, Era::Immortal, 0, Default::default(), Default::default())
     }
 }
 
@@ -57,14 +108,183 @@ impl Balances for Runtime {
 
 impl Exchange for Runtime {
     type Pair = substrate_primitives::sr25519::Pair;
+    type Call = node_runtime::Call;
+
+    fn transfer_call(
+        dest: <<Self as System>::Lookup as StaticLookup>::Source,
+        amount: <Self as Balances>::Balance,
+    ) -> Self::Call {
+        node_runtime::Call::Balances(srml_balances::Call::transfer(dest, amount))
+    }
+
+    fn transfer_amount(call: &Self::Call) -> Option<<Self as Balances>::Balance> {
+        match call {
+            node_runtime::Call::Balances(srml_balances::Call::transfer(_, amount)) => {
+                Some(*amount)
+            }
+            _ => None,
+        }
+    }
+
+    fn extrinsic_outcome(event: &<Self as System>::Event) -> Option<Outcome> {
+        match event {
+            node_runtime::Event::system(srml_system::Event::ExtrinsicSuccess) => {
+                Some(Outcome::Success)
+            }
+            node_runtime::Event::system(srml_system::Event::ExtrinsicFailed(error)) => {
+                Some(Outcome::Failed(DispatchFailure {
+                    module_index: error.module,
+                    error_index: error.error,
+                    module: None,
+                    error: None,
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// `force_transfer` emits `Transfer` too. Staking rewards, `set_balance`
+    /// and the endowments of the genesis block emit no event naming the
+    /// credited account, so they aren't reported.
+    fn balance_credit(event: &<Self as System>::Event) -> Option<Credit<Self>> {
+        match event {
+            node_runtime::Event::balances(srml_balances::RawEvent::Transfer(
+                from,
+                to,
+                amount,
+                _,
+            )) => {
+                Some(Credit {
+                    from: Some(from.clone()),
+                    to: to.clone(),
+                    amount: *amount,
+                })
+            }
+            node_runtime::Event::treasury(srml_treasury::RawEvent::Awarded(_, amount, to)) => {
+                Some(Credit {
+                    from: None,
+                    to: to.clone(),
+                    amount: *amount,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn signed_extra(
+        nonce: <Self as System>::Index,
+        era: Era,
+        tip: <Self as Balances>::Balance,
+        genesis_hash: <Self as System>::Hash,
+        era_hash: <Self as System>::Hash,
+    ) -> Self::SignedExtra {
+        (
+            CheckGenesis(genesis_hash),
+            CheckEra(era, era_hash),
+            srml_system::CheckNonce::from(nonce),
+            srml_system::CheckWeight::new(),
+            srml_balances::TakeFees::from(tip),
+        )
+    }
+
+    fn call_weight(call: &Self::Call) -> u32 {
+        call.get_dispatch_info().weight
+    }
+
+    fn weight_to_fee(weight: u32) -> <Self as Balances>::Balance {
+        <node_runtime::Runtime as srml_balances::Trait>::WeightToFee::convert(weight)
+    }
+}
+
+/// Command line options.
+#[derive(StructOpt)]
+struct Opt {
+    /// Encrypted keystore file.
+    #[structopt(long = "keystore", parse(from_os_str))]
+    keystore: Option<PathBuf>,
+    /// Database directory.
+    #[structopt(long = "db", default_value = "substrate-exchange.db", parse(from_os_str))]
+    db: PathBuf,
+    /// Ss58 address of the master account deposit addresses are derived from.
+    #[structopt(long = "deposit-master")]
+    deposit_master: Option<String>,
+    /// Number of blocks a signed transfer stays valid for, 0 for transfers
+    /// that never expire.
+    #[structopt(long = "era-period", default_value = "64")]
+    era_period: u64,
+    /// Tip paid by transfers not specifying one, at most `--max-tip`.
+    #[structopt(long = "tip", default_value = "0")]
+    tip: u128,
+    /// Highest tip a transfer may pay.
+    #[structopt(long = "max-tip", default_value = "0")]
+    max_tip: u128,
+    /// Number of decimals of one whole token.
+    #[structopt(long = "decimals", default_value = "0")]
+    decimals: u8,
+    /// Token symbol.
+    #[structopt(long = "symbol", default_value = "")]
+    symbol: String,
+    /// Number of confirmations after which deposits are credited, instead of
+    /// waiting for finality.
+    #[structopt(long = "confirmations")]
+    confirmations: Option<u64>,
+    /// Url notified of deposits and withdrawals, can be repeated.
+    #[structopt(long = "webhook")]
+    webhooks: Vec<String>,
+    /// File containing the key webhook notifications are signed with,
+    /// required with `--webhook`.
+    #[structopt(long = "webhook-secret-file", parse(from_os_str))]
+    webhook_secret_file: Option<PathBuf>,
+    /// Number of subscriptions a WebSocket connection may hold.
+    #[structopt(long = "max-subscriptions", default_value = "16")]
+    max_subscriptions: usize,
+    /// Number of balance subscriptions of all WebSocket connections together.
+    #[structopt(long = "max-balance-subscriptions", default_value = "1024")]
+    max_balance_subscriptions: usize,
+    /// Number of WebSocket connections served at once.
+    #[structopt(long = "max-connections", default_value = "100")]
+    max_connections: usize,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Add a key to the keystore, reading the suri and passphrase from stdin.
+    #[structopt(name = "add-key")]
+    AddKey {
+        /// Key id of the new account.
+        id: String,
+    },
+    /// Sign a transfer offline, reading the passphrase from stdin.
+    #[structopt(name = "sign")]
+    Sign {
+        /// Key id of the signing account.
+        id: String,
+        /// File containing the unsigned transfer.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// File to write the signed transfer to.
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
 }
 
 #[derive(Debug)]
 enum Error {
+    Exchange(ExchangeError),
     Io(std::io::Error),
+    NoKeystore,
     AddrParse(std::net::AddrParseError),
     UrlParse(url::ParseError),
     Subxt(SubError),
+    Ws(jsonrpc_ws_server::Error),
+}
+
+impl From<ExchangeError> for Error {
+    fn from(error: ExchangeError) -> Self {
+        Error::Exchange(error)
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -79,6 +299,12 @@ impl From<std::net::AddrParseError> for Error {
     }
 }
 
+impl From<jsonrpc_ws_server::Error> for Error {
+    fn from(error: jsonrpc_ws_server::Error) -> Self {
+        Error::Ws(error)
+    }
+}
+
 impl From<url::ParseError> for Error {
     fn from(error: url::ParseError) -> Self {
         Error::UrlParse(error)
@@ -91,31 +317,148 @@ impl From<SubError> for Error {
     }
 }
 
+/// Reads a secret line from stdin without echoing it to the terminal.
+fn read_secret(prompt: &str) -> Result<Zeroizing<String>, Error> {
+    Ok(Zeroizing::new(rpassword::prompt_password_stderr(prompt)?))
+}
+
+/// Adds a key read from stdin to the keystore at `path`.
+fn add_key(path: &Path, id: &str) -> Result<(), Error> {
+    let mut keystore = Keystore::<<Runtime as Exchange>::Pair>::open(path)?;
+    let suri = read_secret("suri: ")?;
+    let passphrase = read_secret("passphrase: ")?;
+    let public = keystore.add(id, &suri, &passphrase)?;
+    log::info!("added key {} for {}", id, public.to_ss58check());
+    Ok(())
+}
+
+/// Signs the unsigned transfer in `input` with the key `id` and writes the
+/// signed transfer to `output`.
+fn sign(keystore: &Path, id: &str, input: &Path, output: &Path) -> Result<(), Error> {
+    let mut keystore = Keystore::<<Runtime as Exchange>::Pair>::open(keystore)?;
+    let transfer = UnsignedTransfer::<Runtime>::from_hex(&std::fs::read_to_string(input)?)?;
+    log::info!(
+        "signing {:?} from {} with nonce {}",
+        transfer.call,
+        transfer.signer.to_ss58check(),
+        transfer.nonce,
+    );
+    let passphrase = read_secret("passphrase: ")?;
+    keystore.unlock(&passphrase)?;
+    let signed = transfer.sign(keystore.get(id)?)?;
+    keystore.lock();
+    std::fs::write(output, offline::to_hex(&signed))?;
+    Ok(())
+}
+
 /// Check alices account balance with curl:
 /// curl -X POST -H "content-type: application/json" -d
 /// '{"jsonrpc":"2.0","id":0,"method":"account_balance","params":["5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"]}'
 /// http://127.0.0.1:3030
-/// Transfer 10 from alice to bob with curl:
+/// Transfer 10 from alice to bob with curl, where `alice` is a key id from the
+/// keystore:
+/// curl -X POST -H "content-type: application/json" -d
+/// '{"jsonrpc":"2.0","id":0,"method":"transfer_balance","params":["alice", "5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty", "0xa"]}'
+/// http://127.0.0.1:3030
+/// Transfer 10 from alice to bob and wait for finality with curl:
 /// curl -X POST -H "content-type: application/json" -d
-/// '{"jsonrpc":"2.0","id":0,"method":"transfer_balance","params":["//Alice", "5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty", "0xa"]}'
+/// '{"jsonrpc":"2.0","id":0,"method":"transfer_balance","params":["alice", "5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty", "10", {"wait":"finalized"}]}'
 /// http://127.0.0.1:3030
+/// Transfer 1.25 whole tokens, given `--decimals`, from alice to bob with curl:
+/// curl -X POST -H "content-type: application/json" -d
+/// '{"jsonrpc":"2.0","id":0,"method":"transfer_balance","params":["alice", "5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty", "1.25"]}'
+/// http://127.0.0.1:3030
+/// Subscribe to the balance of bob with websocat, sending the request line:
+/// websocat ws://127.0.0.1:3031
+/// {"jsonrpc":"2.0","id":0,"method":"subscribe_account_balance","params":["5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty"]}
 fn main() -> Result<(), Error> {
     env_logger::init();
+    let opt = Opt::from_args();
+
+    match &opt.cmd {
+        Some(Command::AddKey { id }) => {
+            let path = opt.keystore.as_ref().ok_or(Error::NoKeystore)?;
+            return add_key(path, id)
+        }
+        Some(Command::Sign { id, input, output }) => {
+            let path = opt.keystore.as_ref().ok_or(Error::NoKeystore)?;
+            return sign(path, id, input, output)
+        }
+        None => {}
+    }
+
+    let mut keystore = match &opt.keystore {
+        Some(path) => Keystore::open(path)?,
+        None => Keystore::new(),
+    };
+    if keystore.is_locked() {
+        let passphrase = read_secret("passphrase: ")?;
+        keystore.unlock(&passphrase)?;
+        log::info!("keystore unlocked");
+    }
+
+    let db = Db::open(&opt.db)?;
+    let mut config = Config::<Runtime>::default();
+    if let Some(master) = &opt.deposit_master {
+        let master = <<Runtime as Exchange>::Pair as Pair>::Public::from_string(master)
+            .map_err(|_| ExchangeError::InvalidSS58)?;
+        config.deposit_master = Some(master);
+    }
+    config.era_period = opt.era_period;
+    config.tip = opt.tip;
+    config.max_tip = opt.max_tip;
+    config.denomination = Denomination {
+        decimals: opt.decimals,
+        symbol: opt.symbol.clone(),
+    };
+    if let Some(confirmations) = opt.confirmations {
+        config.credit_policy = CreditPolicy::Confirmations(confirmations);
+    }
+    config.webhooks = WebhookConfig {
+        urls: opt.webhooks.clone(),
+        secret: match &opt.webhook_secret_file {
+            Some(path) => std::fs::read_to_string(path)?.trim_end().as_bytes().to_vec(),
+            None => Vec::new(),
+        },
+    };
+    if !config.webhooks.urls.is_empty() && config.webhooks.secret.is_empty() {
+        return Err(ExchangeError::EmptyWebhookSecret.into())
+    }
+    config.max_subscriptions = opt.max_subscriptions;
+    config.max_balance_subscriptions = opt.max_balance_subscriptions;
 
     let addr = "127.0.0.1:3030".parse()?;
     log::info!("starting server at {}", addr);
 
+    let ws_addr = "127.0.0.1:3031".parse()?;
+    log::info!("starting websocket server at {}", ws_addr);
+
     let url = "ws://127.0.0.1:9944".parse()?;
     log::info!("connecting to substrate at {}", url);
 
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let node = rt.block_on(Node::connect(&url))?;
     let client = {
         let client = ClientBuilder::<Runtime>::new()
             .set_url(url)
             .build();
-        let mut rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(client)?
     };
-    let rpc = RpcImpl::new(client);
+    let rpc = RpcImpl::new(client, node, keystore, db, config)?;
+    rt.block_on(rpc.recover())?;
+    rt.spawn(rpc.background());
+    // The WebSocket server only serves subscriptions, the methods stay on
+    // the http server.
+    let mut ws_io = PubSubHandler::new(MetaIoHandler::default());
+    ws_io.extend_with(rpc.subscriptions().to_delegate());
+    let _ws_server = WsServerBuilder::with_meta_extractor(ws_io, |context: &RequestContext| {
+        Meta {
+            session: Some(Arc::new(Session::new(context.sender()))),
+            connection: context.session_id,
+        }
+    })
+    .max_connections(opt.max_connections)
+    .start(&ws_addr)?;
     let mut io = IoHandler::new();
     io.extend_with(rpc.to_delegate());
     let server = ServerBuilder::new(io).start_http(&addr)?;
@@ -127,14 +470,33 @@ fn main() -> Result<(), Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use substrate_keyring::sr25519::Keyring;
-    use substrate_primitives::crypto::{
-        Pair,
-        Ss58Codec,
+    use futures::Stream;
+    use jsonrpc_core::ErrorCode;
+    use serde_json::json;
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+    use std::{
+        io::{
+            BufRead,
+            BufReader,
+            Read,
+            Write,
+        },
+        net::TcpListener,
+        sync::mpsc,
     };
+    use substrate_exchange::{
+        signature,
+        DepositState,
+        INSUFFICIENT_FUNDS,
+        SIGNATURE_HEADER,
+    };
+    use substrate_keyring::sr25519::Keyring;
 
     fn key(keyring: Keyring) -> String {
-        format!("//{}", <&'static str>::from(keyring))
+        <&'static str>::from(keyring).to_lowercase()
     }
 
     fn pubkey(keyring: Keyring) -> String {
@@ -152,7 +514,7 @@ mod tests {
     ) -> u128 {
         let balance = rpc.account_balance(pubkey(of));
         let result = rt.block_on(balance).unwrap();
-        result.parse().unwrap()
+        result.value.parse().unwrap()
     }
 
     fn transfer_balance(
@@ -161,17 +523,76 @@ mod tests {
         from: Keyring,
         to: Keyring,
         amount: u128,
-    ) {
-        let transfer = rpc.transfer_balance(key(from), pubkey(to), balance(amount));
-        rt.block_on(transfer).unwrap();
+    ) -> TransferResult {
+        let options = TransferOptions {
+            wait: Wait::InBlock,
+            ..Default::default()
+        };
+        let transfer =
+            rpc.transfer_balance(key(from), pubkey(to), balance(amount), Some(options));
+        let result = rt.block_on(transfer).unwrap();
+        assert!(result.block.is_some());
+        result
+    }
+
+    /// Returns the address of the account derived from the empty seed along
+    /// the path `//<name>`.
+    fn derived(name: &str) -> String {
+        let path = format!("//{}", name);
+        <<Runtime as Exchange>::Pair as Pair>::from_string(&path, None)
+            .unwrap()
+            .public()
+            .to_ss58check()
+    }
+
+    /// Calls `poll` every 100ms until it returns a value, for at most 30s.
+    fn poll_until<R>(mut poll: impl FnMut() -> Option<R>) -> R {
+        for _ in 0..300 {
+            if let Some(result) = poll() {
+                return result
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        panic!("timed out polling")
+    }
+
+    fn temp_db() -> Db {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let name = format!("substrate-exchange-{}-{}", std::process::id(), n);
+        let path = std::env::temp_dir().join(name);
+        std::fs::remove_dir_all(&path).ok();
+        Db::open(path).unwrap()
+    }
+
+    fn start(rt: &mut tokio::runtime::Runtime, db: Db) -> RpcImpl<Runtime> {
+        start_with_config(rt, db, Config::default())
+    }
+
+    fn start_with_config(
+        rt: &mut tokio::runtime::Runtime,
+        db: Db,
+        config: Config<Runtime>,
+    ) -> RpcImpl<Runtime> {
+        let client = ClientBuilder::<Runtime>::new().build();
+        let client = rt.block_on(client).unwrap();
+        let url = "ws://127.0.0.1:9944".parse().unwrap();
+        let node = rt.block_on(Node::connect(&url)).unwrap();
+        let mut keystore = Keystore::new();
+        for keyring in &[Keyring::Alice, Keyring::Bob, Keyring::Charlie] {
+            let suri = format!("//{}", <&'static str>::from(*keyring));
+            keystore.insert_suri(&key(*keyring), &suri).unwrap();
+        }
+        let rpc = RpcImpl::new(client, node, keystore, db, config).unwrap();
+        rt.block_on(rpc.recover()).unwrap();
+        rt.spawn(rpc.background());
+        rpc
     }
 
     fn test_setup() -> (tokio::runtime::Runtime, RpcImpl<Runtime>) {
         env_logger::try_init().ok();
         let mut rt = tokio::runtime::Runtime::new().unwrap();
-        let client = ClientBuilder::<Runtime>::new().build();
-        let client = rt.block_on(client).unwrap();
-        let rpc = RpcImpl::new(client);
+        let rpc = start(&mut rt, temp_db());
         (rt, rpc)
     }
 
@@ -181,10 +602,495 @@ mod tests {
         account_balance(&mut rt, &rpc, Keyring::Alice);
     }
 
+    #[test]
+    fn test_account_balance_details() {
+        let (mut rt, rpc) = test_setup();
+        let free = account_balance(&mut rt, &rpc, Keyring::Alice);
+        let details = rpc.account_balance_details(pubkey(Keyring::Alice), None);
+        let details = rt.block_on(details).unwrap();
+        let transferable: u128 = details.transferable.value.parse().unwrap();
+        assert_eq!(details.free.value, free.to_string());
+        assert!(transferable <= free);
+    }
+
+    #[test]
+    fn test_account_balance_at_block() {
+        let (mut rt, rpc) = test_setup();
+        let details = rpc.account_balance_details(pubkey(Keyring::Alice), Some(At::Number(0)));
+        let genesis = rt.block_on(details).unwrap();
+        assert_eq!(genesis.block_number, 0);
+        let at = Some(At::Hash(genesis.block_hash.clone()));
+        let details = rpc.account_balance_details(pubkey(Keyring::Alice), at);
+        let details = rt.block_on(details).unwrap();
+        assert_eq!(details.block_hash, genesis.block_hash);
+        assert_eq!(details.free, genesis.free);
+    }
+
+    #[test]
+    fn test_accounts_balances() {
+        let (mut rt, rpc) = test_setup();
+        let addresses = vec![pubkey(Keyring::Alice), "invalid".to_string()];
+        let balances = rpc.accounts_balances(addresses, None);
+        let balances = rt.block_on(balances).unwrap();
+        assert_eq!(balances.balances.len(), 2);
+        let alice = balances.balances[0].balance.as_ref().unwrap();
+        assert_eq!(alice.block_hash, balances.block_hash);
+        assert!(balances.balances[1].balance.is_none());
+        assert!(balances.balances[1].error.is_some());
+    }
+
     #[test]
     fn test_transfer_balance() {
         let (mut rt, rpc) = test_setup();
-        transfer_balance(&mut rt, &rpc, Keyring::Alice, Keyring::Bob, 10_000);
+        let result = transfer_balance(&mut rt, &rpc, Keyring::Alice, Keyring::Bob, 10_000);
+        let era = result.era.unwrap();
+        let block = result.block.unwrap();
+        assert!(era.birth <= block.number && block.number < era.death);
+    }
+
+    #[test]
+    fn test_transaction_status() {
+        let (mut rt, rpc) = test_setup();
+        let result = transfer_balance(&mut rt, &rpc, Keyring::Alice, Keyring::Bob, 10_000);
+        let status = rpc.transaction_status(result.hash).unwrap();
+        assert!(status.reached(Wait::InBlock));
+        assert!(status.state != TxState::Dropped && status.state != TxState::Invalid);
+        assert_eq!(status.outcome, Some(Outcome::Success));
+    }
+
+    #[test]
+    fn test_recover_outbox() {
+        env_logger::try_init().ok();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let db = temp_db();
+        let rpc = start(&mut rt, db.clone());
+        let result = transfer_balance(&mut rt, &rpc, Keyring::Alice, Keyring::Bob, 10_000);
+        drop(rpc);
+        let rpc = start(&mut rt, db);
+        let status = rpc.transaction_status(result.hash).unwrap();
+        assert!(status.reached(Wait::InBlock));
+        assert!(status.state != TxState::Dropped && status.state != TxState::Invalid);
+    }
+
+    #[test]
+    fn test_repeated_request_id() {
+        let (mut rt, rpc) = test_setup();
+        let mut transfer = |amount| {
+            let options = TransferOptions {
+                request_id: Some("withdrawal-1".into()),
+                ..Default::default()
+            };
+            let transfer = rpc.transfer_balance(
+                key(Keyring::Alice),
+                pubkey(Keyring::Bob),
+                balance(amount),
+                Some(options),
+            );
+            rt.block_on(transfer)
+        };
+        let first = transfer(10_000).unwrap();
+        let second = transfer(10_000).unwrap();
+        assert_eq!(first.hash, second.hash);
+        assert_eq!(first.nonce, second.nonce);
+        assert_eq!(first.amount.value, second.amount.value);
+        assert_eq!(first.fee.value, second.fee.value);
+        assert!(transfer(20_000).is_err());
+    }
+
+    #[test]
+    fn test_estimate_transfer_fee() {
+        let (mut rt, rpc) = test_setup();
+        let estimate = rpc.estimate_transfer_fee(
+            key(Keyring::Alice),
+            pubkey(Keyring::Bob),
+            balance(10_000),
+            None,
+        );
+        let estimate = rt.block_on(estimate).unwrap();
+        let total: u128 = estimate.total.value.parse().unwrap();
+        let base_fee: u128 = estimate.base_fee.value.parse().unwrap();
+        assert!(estimate.length > 0);
+        assert!(total >= base_fee);
+    }
+
+    #[test]
+    fn test_tip_above_maximum() {
+        let (mut rt, rpc) = test_setup();
+        let options = TransferOptions {
+            tip: Some(balance(1)),
+            ..Default::default()
+        };
+        let transfer = rpc.transfer_balance(
+            key(Keyring::Alice),
+            pubkey(Keyring::Bob),
+            balance(10_000),
+            Some(options),
+        );
+        assert!(rt.block_on(transfer).is_err());
+    }
+
+    #[test]
+    fn test_default_tip_above_maximum() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let client = rt.block_on(ClientBuilder::<Runtime>::new().build()).unwrap();
+        let url = "ws://127.0.0.1:9944".parse().unwrap();
+        let node = rt.block_on(Node::connect(&url)).unwrap();
+        let mut config = Config::default();
+        config.tip = 2;
+        config.max_tip = 1;
+        let rpc = RpcImpl::new(client, node, Keystore::new(), temp_db(), config);
+        assert!(rpc.is_err());
+    }
+
+    #[test]
+    fn test_transfer_would_reap_sender() {
+        let (mut rt, rpc) = test_setup();
+        let free = account_balance(&mut rt, &rpc, Keyring::Alice);
+        let estimate = rpc.estimate_transfer_fee(
+            key(Keyring::Alice),
+            pubkey(Keyring::Bob),
+            balance(free),
+            None,
+        );
+        let fee: u128 = rt.block_on(estimate).unwrap().total.value.parse().unwrap();
+        let transfer = rpc.transfer_balance(
+            key(Keyring::Alice),
+            pubkey(Keyring::Bob),
+            balance(free - fee),
+            None,
+        );
+        match rt.block_on(transfer) {
+            Err(err) => assert_eq!(err, ExchangeError::WouldReapSender.into()),
+            Ok(result) => panic!("transfer of the whole balance succeeded: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_transfer_insufficient_funds() {
+        let (mut rt, rpc) = test_setup();
+        let free = account_balance(&mut rt, &rpc, Keyring::Alice);
+        let transfer =
+            rpc.transfer_balance(key(Keyring::Alice), pubkey(Keyring::Bob), balance(free), None);
+        match rt.block_on(transfer) {
+            Err(err) => assert_eq!(err.code, ErrorCode::ServerError(INSUFFICIENT_FUNDS)),
+            Ok(result) => panic!("transfer of the whole balance succeeded: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_transfer_below_existential_deposit() {
+        let (mut rt, rpc) = test_setup();
+        let unfunded = derived("Unfunded");
+        let transfer = rpc.transfer_balance(key(Keyring::Alice), unfunded, balance(1), None);
+        match rt.block_on(transfer) {
+            Err(err) => assert_eq!(err, ExchangeError::BelowExistentialDeposit.into()),
+            Ok(result) => panic!("transfer below the existential deposit succeeded: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_transfer_sweep() {
+        let (mut rt, rpc) = test_setup();
+        let funding = 1_000_000_000_000_000;
+        transfer_balance(&mut rt, &rpc, Keyring::Alice, Keyring::Charlie, funding);
+        let free = account_balance(&mut rt, &rpc, Keyring::Charlie);
+        let options = TransferOptions {
+            wait: Wait::InBlock,
+            ..Default::default()
+        };
+        let sweep = rpc.transfer_sweep(key(Keyring::Charlie), pubkey(Keyring::Bob), Some(options));
+        let result = rt.block_on(sweep).unwrap();
+        let amount: u128 = result.amount.value.parse().unwrap();
+        let fee: u128 = result.fee.value.parse().unwrap();
+        assert_eq!(account_balance(&mut rt, &rpc, Keyring::Charlie), free - amount - fee);
+        let sweep = rpc.transfer_sweep(key(Keyring::Charlie), pubkey(Keyring::Bob), None);
+        match rt.block_on(sweep) {
+            Err(err) => assert_eq!(err, ExchangeError::NothingToSweep.into()),
+            Ok(result) => panic!("sweep of a swept account succeeded: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_deposit_to_watched_address() {
+        let (mut rt, rpc) = test_setup();
+        let address = derived("Deposit");
+        let watched = rpc.deposit_watch_address(address.clone(), Some("customer".into()));
+        assert_eq!(watched.unwrap().address, address);
+        let options = TransferOptions {
+            wait: Wait::Finalized,
+            ..Default::default()
+        };
+        let amount = 1_000_000_000_000_000;
+        let transfer = rpc.transfer_balance(
+            key(Keyring::Alice),
+            address.clone(),
+            balance(amount),
+            Some(options),
+        );
+        let result = rt.block_on(transfer).unwrap();
+        let block = result.block.unwrap();
+        // The indexer processes the block independently of the tracker.
+        let deposits = poll_until(|| {
+            let deposits = rpc.deposit_credit_ready(None).unwrap().deposits;
+            Some(deposits).filter(|deposits| !deposits.is_empty())
+        });
+        assert_eq!(deposits.len(), 1);
+        let deposit = &deposits[0];
+        assert_eq!(deposit.state, DepositState::Finalized);
+        assert!(deposit.confirmations >= 1);
+        let ready = rpc.deposit_credit_ready(deposit.cursor).unwrap();
+        assert!(ready.deposits.is_empty());
+        assert_eq!(ready.cursor, deposit.cursor.unwrap());
+        assert_eq!(rpc.deposit_list(Some(address.clone())).unwrap().len(), 1);
+        assert_eq!(deposit.label, Some("customer".into()));
+        assert_eq!(deposit.from, Some(pubkey(Keyring::Alice)));
+        assert_eq!(deposit.amount.value, amount.to_string());
+        assert_eq!(deposit.block_hash, block.hash);
+        assert_eq!(deposit.extrinsic_index, Some(block.index));
+        assert!(rpc.deposit_unwatch_address(address).unwrap());
+    }
+
+    #[test]
+    fn test_deposit_backfill() {
+        let (mut rt, rpc) = test_setup();
+        let address = derived("Backfill");
+        let amount = 1_000_000_000_000_000;
+        let options = TransferOptions {
+            wait: Wait::InBlock,
+            ..Default::default()
+        };
+        let transfer = rpc.transfer_balance(
+            key(Keyring::Alice),
+            address.clone(),
+            balance(amount),
+            Some(options),
+        );
+        let block = rt.block_on(transfer).unwrap().block.unwrap();
+        // The deposit was made before the address was watched.
+        rpc.deposit_watch_address(address.clone(), None).unwrap();
+        assert!(rpc.deposit_list(Some(address.clone())).unwrap().is_empty());
+        let backfill = rpc.deposit_backfill(block.number, Some(block.number));
+        let backfill = rt.block_on(backfill).unwrap();
+        assert_eq!(backfill.deposits.len(), 1);
+        assert_eq!(backfill.deposits[0].block_hash, block.hash);
+        assert_eq!(backfill.deposits[0].amount.value, amount.to_string());
+        // Replaying the block again doesn't record the deposit twice.
+        let backfill = rpc.deposit_backfill(block.number.saturating_sub(1), None);
+        assert!(rt.block_on(backfill).unwrap().deposits.is_empty());
+        assert_eq!(rpc.deposit_list(Some(address.clone())).unwrap().len(), 1);
+        assert!(rpc.deposit_checkpoint().unwrap().is_some());
+        assert!(rpc.deposit_unwatch_address(address).unwrap());
+    }
+
+    /// Starts a webhook receiver answering the first request with an error,
+    /// and forwards the signature header and body of every request.
+    fn webhook_receiver() -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/notify", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut signature = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break
+                    }
+                    let (name, value) = line.split_at(line.find(':').unwrap_or(0));
+                    let value = value.trim_start_matches(':').trim();
+                    if name.eq_ignore_ascii_case(SIGNATURE_HEADER) {
+                        signature = value.to_string();
+                    } else if name.eq_ignore_ascii_case("content-length") {
+                        length = value.parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                // The failed delivery must be retried.
+                let status = if i == 0 { "500 Internal Server Error" } else { "200 OK" };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+                if i > 0 {
+                    let body = String::from_utf8(body).unwrap();
+                    if sender.send((signature, body)).is_err() {
+                        break
+                    }
+                }
+            }
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn test_webhooks() {
+        env_logger::try_init().ok();
+        let (url, receiver) = webhook_receiver();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut config = Config::default();
+        config.webhooks = WebhookConfig {
+            urls: vec![url],
+            secret: b"secret".to_vec(),
+        };
+        let rpc = start_with_config(&mut rt, temp_db(), config);
+        let address = derived("Webhook");
+        rpc.deposit_watch_address(address.clone(), None).unwrap();
+        let options = TransferOptions {
+            wait: Wait::Finalized,
+            ..Default::default()
+        };
+        let transfer = rpc.transfer_balance(
+            key(Keyring::Alice),
+            address.clone(),
+            balance(1_000_000_000_000_000),
+            Some(options),
+        );
+        let result = rt.block_on(transfer).unwrap();
+        let mut events = Vec::new();
+        while events.len() < 4 {
+            let timeout = std::time::Duration::from_secs(30);
+            let (header, body) = receiver.recv_timeout(timeout).unwrap();
+            assert_eq!(header, format!("sha256={}", signature(b"secret", body.as_bytes())));
+            let notification: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let event = notification["event"].as_str().unwrap().to_string();
+            if event.starts_with("withdrawal") {
+                assert_eq!(notification["data"]["hash"], result.hash.as_str());
+            } else {
+                assert_eq!(notification["data"]["address"], address.as_str());
+            }
+            events.push(event);
+        }
+        events.sort();
+        let expected = vec![
+            "deposit_detected",
+            "deposit_finalized",
+            "withdrawal_finalized",
+            "withdrawal_included",
+        ];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        env_logger::try_init().ok();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut config = Config::default();
+        config.max_subscriptions = 2;
+        let rpc = start_with_config(&mut rt, temp_db(), config);
+        let mut io = PubSubHandler::new(MetaIoHandler::default());
+        io.extend_with(rpc.subscriptions().to_delegate());
+        let (sender, receiver) = futures::sync::mpsc::channel(16);
+        let meta = Meta {
+            session: Some(Arc::new(Session::new(sender))),
+            connection: 1,
+        };
+        let call = |method: &str, params: serde_json::Value| {
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": method,
+                "params": params,
+            });
+            let response = io.handle_request_sync(&request.to_string(), meta.clone()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&response).unwrap()
+        };
+        let address = derived("Subscriber");
+        rpc.deposit_watch_address(address.clone(), None).unwrap();
+        let balances = call("subscribe_account_balance", json!([address]))["result"].clone();
+        let deposits = call("subscribe_deposits", json!([address]))["result"].clone();
+        assert!(balances.is_number() && deposits.is_number());
+        // The connection reached its limit of two subscriptions.
+        let rejected = call("subscribe_deposits", json!([null]));
+        let limit = "The connection reached its subscription limit.";
+        assert_eq!(rejected["error"]["message"], limit);
+
+        let amount = 1_000_000_000_000_000u128;
+        let options = TransferOptions {
+            wait: Wait::InBlock,
+            ..Default::default()
+        };
+        let transfer = rpc.transfer_balance(
+            key(Keyring::Alice),
+            address.clone(),
+            balance(amount),
+            Some(options),
+        );
+        let block = rt.block_on(transfer).unwrap().block.unwrap();
+        let (mut funded, mut deposited) = (false, false);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+        let mut receiver = receiver;
+        loop {
+            let next = tokio::timer::Timeout::new_at(receiver.into_future(), deadline);
+            let (notification, rest) = match rt.block_on(next) {
+                Ok((Some(notification), rest)) => (notification, rest),
+                Ok((None, _)) => panic!("notifications ended before the deposit"),
+                Err(_) => panic!("no balance and deposit notification within 60s"),
+            };
+            receiver = rest;
+            let notification: serde_json::Value =
+                serde_json::from_str(&notification).unwrap();
+            let params = &notification["params"];
+            match notification["method"].as_str().unwrap() {
+                "account_balance" => {
+                    assert_eq!(params["subscription"], balances);
+                    funded |= params["result"]["free"]["value"] == amount.to_string().as_str();
+                }
+                "deposit" => {
+                    assert_eq!(params["subscription"], deposits);
+                    assert_eq!(params["result"]["address"], address.as_str());
+                    assert_eq!(params["result"]["block_hash"], block.hash.as_str());
+                    deposited = true;
+                }
+                method => panic!("unexpected notification {}", method),
+            }
+            if funded && deposited {
+                break
+            }
+        }
+
+        let unsubscribe = call("unsubscribe_account_balance", json!([balances]));
+        assert_eq!(unsubscribe["result"], true);
+        let unsubscribe = call("unsubscribe_account_balance", json!([balances]));
+        assert_eq!(unsubscribe["result"], false);
+        let unsubscribe = call("unsubscribe_deposits", json!([deposits]));
+        assert_eq!(unsubscribe["result"], true);
+    }
+
+    #[test]
+    fn test_transfer_from_unknown_account() {
+        let (mut rt, rpc) = test_setup();
+        let transfer = rpc.transfer_balance(
+            "//Alice".into(),
+            pubkey(Keyring::Bob),
+            balance(10_000),
+            None,
+        );
+        match rt.block_on(transfer) {
+            Err(err) => assert_eq!(err, ExchangeError::UnknownAccount.into()),
+            Ok(result) => panic!("transfer from an unknown account succeeded: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_offline_transfer() {
+        let (mut rt, rpc) = test_setup();
+        let payload = rpc.transfer_payload(
+            pubkey(Keyring::Alice),
+            pubkey(Keyring::Bob),
+            balance(10_000),
+        );
+        let payload = rt.block_on(payload).unwrap();
+        let transfer = UnsignedTransfer::<Runtime>::from_hex(&payload).unwrap();
+        let signed = transfer.sign(&Keyring::Alice.pair()).unwrap();
+        let submit = rpc.submit_signed_transfer(offline::to_hex(&signed));
+        rt.block_on(submit).unwrap();
     }
 
     #[test]
@@ -197,10 +1103,11 @@ mod tests {
 
         let balance_from = account_balance(&mut rt, &rpc, from);
         let balance_to = account_balance(&mut rt, &rpc, to);
-        transfer_balance(&mut rt, &rpc, from, to, amount);
+        let result = transfer_balance(&mut rt, &rpc, from, to, amount);
+        let fee: u128 = result.fee.value.parse().unwrap();
         let balance_from2 = account_balance(&mut rt, &rpc, from);
         let balance_to2 = account_balance(&mut rt, &rpc, to);
-        assert_eq!(balance_from, balance_from2 + amount);
+        assert_eq!(balance_from, balance_from2 + amount + fee);
         assert_eq!(balance_to + amount, balance_to2);
     }
 }
@@ -0,0 +1,141 @@
+//! Error names of the runtime metadata of the node.
+//!
+//! A dispatch error only carries the index of the module and of the error.
+//! Their names are looked up in the metadata of the runtime the node runs at
+//! the block of the extrinsic, so the names stay right across runtime
+//! upgrades. The metadata is fetched once per runtime spec version.
+use crate::{
+    node::Node,
+    tracker::DispatchFailure,
+    Error,
+    Exchange,
+};
+use futures::future::{
+    self,
+    Either,
+    Future,
+};
+use parity_scale_codec::Decode;
+use srml_metadata::{
+    DecodeDifferent,
+    RuntimeMetadata,
+    RuntimeMetadataPrefixed,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+/// Name and error names of a module, in the order of the metadata.
+type Modules = Vec<(String, Vec<String>)>;
+
+/// Error names of the runtimes of the node by spec version.
+pub struct Metadata<T: Exchange> {
+    node: Node<T>,
+    modules: Arc<Mutex<HashMap<u32, Arc<Modules>>>>,
+}
+
+impl<T: Exchange> Clone for Metadata<T> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+            modules: self.modules.clone(),
+        }
+    }
+}
+
+impl<T: Exchange> Metadata<T> {
+    /// Creates an empty `Metadata` fetching from `node`.
+    pub fn new(node: Node<T>) -> Self {
+        Self {
+            node,
+            modules: Default::default(),
+        }
+    }
+
+    /// Resolves the module and error name of `failure` raised in the block
+    /// `block_hash`.
+    ///
+    /// Names unknown to the metadata are left `None`.
+    pub fn resolve(
+        &self,
+        failure: DispatchFailure,
+        block_hash: T::Hash,
+    ) -> impl Future<Item = DispatchFailure, Error = Error> {
+        self.modules(block_hash).map(move |modules| {
+            let module = failure
+                .module_index
+                .and_then(|index| modules.get(index as usize));
+            DispatchFailure {
+                module: module.map(|(name, _)| name.clone()),
+                error: module.and_then(|(_, errors)| {
+                    errors.get(failure.error_index as usize).cloned()
+                }),
+                ..failure
+            }
+        })
+    }
+
+    /// Returns the modules of the runtime at the block `block_hash`, fetching
+    /// its metadata if its spec version is new.
+    fn modules(&self, block_hash: T::Hash) -> impl Future<Item = Arc<Modules>, Error = Error> {
+        let metadata = self.clone();
+        self.node
+            .spec_version(block_hash)
+            .and_then(move |version| {
+                if let Some(modules) = metadata.modules.lock().unwrap().get(&version) {
+                    return Either::A(future::ok(modules.clone()))
+                }
+                let fetched = metadata.node.metadata(block_hash).and_then(move |bytes| {
+                    let modules = Arc::new(decode(&bytes)?);
+                    metadata
+                        .modules
+                        .lock()
+                        .unwrap()
+                        .insert(version, modules.clone());
+                    Ok(modules)
+                });
+                Either::B(fetched)
+            })
+    }
+}
+
+/// Decodes the module and error names of the encoded metadata `bytes`.
+fn decode(bytes: &[u8]) -> Result<Modules, Error> {
+    let modules = match RuntimeMetadataPrefixed::decode(&mut &bytes[..]) {
+        Ok(RuntimeMetadataPrefixed(_, RuntimeMetadata::V8(metadata))) => metadata.modules,
+        _ => return Err(Error::InvalidResponse),
+    };
+    let modules = match modules {
+        DecodeDifferent::Decoded(modules) => modules,
+        DecodeDifferent::Encode(_) => return Err(Error::InvalidResponse),
+    };
+    let modules = modules
+        .into_iter()
+        .map(|module| {
+            let name = match module.name {
+                DecodeDifferent::Decoded(name) => name,
+                DecodeDifferent::Encode(_) => String::new(),
+            };
+            let errors = match module.errors {
+                DecodeDifferent::Decoded(errors) => {
+                    errors
+                        .into_iter()
+                        .map(|error| {
+                            match error.name {
+                                DecodeDifferent::Decoded(name) => name,
+                                DecodeDifferent::Encode(_) => String::new(),
+                            }
+                        })
+                        .collect()
+                }
+                DecodeDifferent::Encode(_) => Vec::new(),
+            };
+            (name, errors)
+        })
+        .collect();
+    Ok(modules)
+}
@@ -0,0 +1,194 @@
+//! Raw json-rpc calls to the node.
+//!
+//! Covers the methods of the node the client doesn't expose. The node is
+//! reached over its own WebSocket connection.
+use crate::{
+    offline,
+    Error,
+    Exchange,
+};
+use futures::future::Future;
+use jsonrpc_core::{
+    ErrorCode,
+    Params,
+};
+use jsonrpc_core_client::{
+    transports::ws,
+    RawClient,
+    RpcError as ClientError,
+};
+use serde::{
+    de::DeserializeOwned,
+    Deserialize,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+};
+use substrate_primitives::storage::StorageKey;
+use url::Url;
+
+/// Weight and fee of an extrinsic reported by the node.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DispatchInfo {
+    /// Dispatch weight of the call.
+    pub weight: u32,
+    /// Fee of the extrinsic without the tip.
+    #[serde(deserialize_with = "deserialize_balance")]
+    pub partial_fee: u128,
+}
+
+/// Changes of the queried storage keys in a block.
+#[derive(Deserialize)]
+struct StorageChangeSet {
+    /// Pairs of a hex encoded key and its hex encoded value, `None` if empty.
+    changes: Vec<(String, Option<String>)>,
+}
+
+/// Version of the runtime of the node.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RuntimeVersion {
+    /// Version of the runtime specification.
+    spec_version: u32,
+}
+
+/// Deserializes a balance sent as a number or as a hex string.
+fn deserialize_balance<'de, D>(deserializer: D) -> Result<u128, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let balance = match Value::deserialize(deserializer)? {
+        Value::Number(number) => number.as_u64().map(u128::from),
+        Value::String(hex) if hex.starts_with("0x") => u128::from_str_radix(&hex[2..], 16).ok(),
+        _ => None,
+    };
+    balance.ok_or_else(|| serde::de::Error::custom("expected a balance"))
+}
+
+/// Connection to the json-rpc interface of the node.
+pub struct Node<T: Exchange> {
+    client: RawClient,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Exchange> Clone for Node<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Exchange> Node<T> {
+    /// Connects to the node at `url`.
+    pub fn connect(url: &Url) -> impl Future<Item = Self, Error = Error> {
+        ws::connect::<RawClient>(url)
+            .map(|client| {
+                Self {
+                    client,
+                    _marker: PhantomData,
+                }
+            })
+            .map_err(|err| substrate_subxt::Error::Rpc(err).into())
+    }
+
+    /// Calls the method `method` with the positional `params`.
+    fn call<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> impl Future<Item = R, Error = Error> {
+        self.client
+            .call_method(method, Params::Array(params))
+            .map_err(|err| substrate_subxt::Error::Rpc(err).into())
+            .and_then(|value| serde_json::from_value(value).map_err(|_| Error::InvalidResponse))
+    }
+
+    /// Returns the weight and fee of the encoded `extrinsic` at the best block,
+    /// `None` if the node has no payment rpc.
+    pub fn query_info(
+        &self,
+        extrinsic: &[u8],
+    ) -> impl Future<Item = Option<DispatchInfo>, Error = Error> {
+        let params = vec![Value::String(format!("0x{}", hex::encode(extrinsic)))];
+        self.client
+            .call_method("payment_queryInfo", Params::Array(params))
+            .then(|result| {
+                match result {
+                    Ok(value) => {
+                        let info = serde_json::from_value(value)
+                            .map_err(|_| Error::InvalidResponse)?;
+                        Ok(Some(info))
+                    }
+                    Err(ClientError::JsonRpcError(ref err))
+                        if err.code == ErrorCode::MethodNotFound =>
+                    {
+                        Ok(None)
+                    }
+                    Err(err) => Err(substrate_subxt::Error::Rpc(err).into()),
+                }
+            })
+    }
+
+    /// Returns the values stored under `keys` in the block `block_hash` with a
+    /// single query, keyed by the raw storage key.
+    ///
+    /// Keys without a value are missing from the result.
+    pub fn storage_at(
+        &self,
+        keys: &[StorageKey],
+        block_hash: T::Hash,
+    ) -> impl Future<Item = HashMap<Vec<u8>, Vec<u8>>, Error = Error> {
+        let keys = keys
+            .iter()
+            .map(|key| Value::String(format!("0x{}", hex::encode(&key.0))))
+            .collect();
+        let hash = Value::String(format!("{:?}", block_hash));
+        self.call::<Vec<StorageChangeSet>>("state_queryStorage", vec![
+            Value::Array(keys),
+            hash.clone(),
+            hash,
+        ])
+        .and_then(|sets| {
+            let mut values = HashMap::new();
+            for (key, value) in sets.into_iter().flat_map(|set| set.changes) {
+                if let Some(value) = value {
+                    values.insert(from_hex(&key)?, from_hex(&value)?);
+                }
+            }
+            Ok(values)
+        })
+    }
+
+    /// Returns the spec version of the runtime at the block `block_hash`.
+    pub fn spec_version(&self, block_hash: T::Hash) -> impl Future<Item = u32, Error = Error> {
+        let hash = Value::String(format!("{:?}", block_hash));
+        self.call::<RuntimeVersion>("state_getRuntimeVersion", vec![hash])
+            .map(|version| version.spec_version)
+    }
+
+    /// Returns the encoded metadata of the runtime at the block `block_hash`.
+    pub fn metadata(&self, block_hash: T::Hash) -> impl Future<Item = Vec<u8>, Error = Error> {
+        let hash = Value::String(format!("{:?}", block_hash));
+        self.call::<String>("state_getMetadata", vec![hash])
+            .and_then(|metadata| from_hex(&metadata))
+    }
+
+    /// Returns the hash of the last finalized block.
+    pub fn finalized_head(&self) -> impl Future<Item = T::Hash, Error = Error> {
+        self.call::<String>("chain_getFinalizedHead", Vec::new())
+            .and_then(|hash| offline::from_hex(&hash).map_err(|_| Error::InvalidResponse))
+    }
+}
+
+/// Decodes the 0x prefixed hex string `hex` of a response.
+fn from_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    if !hex.starts_with("0x") {
+        return Err(Error::InvalidResponse)
+    }
+    hex::decode(&hex[2..]).map_err(|_| Error::InvalidResponse)
+}
@@ -0,0 +1,193 @@
+//! Nonces of managed accounts.
+//!
+//! The next nonce of an account is derived from the nonce stored on chain and
+//! the extrinsics the shim submitted from the account which are still pending
+//! in the transaction pool. Extrinsics the tracker reports as dropped or
+//! invalid no longer count as pending, so a lost extrinsic leaves a gap which
+//! the next transfer fills. Transfers from the same account hold a lock on the
+//! account from reserving the nonce until the transaction pool accepted the
+//! extrinsic.
+//!
+//! Nonces handed out for offline signing stay reserved until the signed
+//! transfer is submitted or the era of the payload ends. Payloads for
+//! immortal transfers keep their nonce reserved until the account nonce moves
+//! past it.
+use crate::{
+    tracker::Tracker,
+    Error,
+    Exchange,
+    PoolError,
+};
+use futures::future::Future;
+use futures_locks::{
+    Mutex as AsyncMutex,
+    MutexGuard,
+};
+use sr_primitives::traits::{
+    One,
+    SaturatedConversion,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use substrate_subxt::{
+    srml::system::{
+        System,
+        SystemStore,
+    },
+    Client,
+};
+
+/// Use of a nonce of a managed account.
+#[derive(Clone, Copy)]
+enum Use<H> {
+    /// The extrinsic with the hash was submitted.
+    Submitted(H),
+    /// The nonce was handed out for offline signing of a payload which is
+    /// valid until the block `death`, `None` if it is immortal.
+    Offline(Option<u64>),
+}
+
+/// Nonces in use of an account.
+type Pending<T> = BTreeMap<<T as System>::Index, Use<<T as System>::Hash>>;
+
+/// Returns `true` if the transaction pool rejected an extrinsic because its
+/// nonce is already used or too far in the future.
+pub fn is_nonce_rejection(err: &Error) -> bool {
+    match err {
+        Error::PoolRejected(PoolError::Invalid(reason)) => reason == "Stale" || reason == "Future",
+        _ => false,
+    }
+}
+
+/// Hands out nonces of managed accounts.
+pub struct NonceManager<T: Exchange> {
+    client: Client<T>,
+    tracker: Tracker<T>,
+    accounts: Arc<Mutex<HashMap<T::AccountId, AsyncMutex<Pending<T>>>>>,
+}
+
+impl<T: Exchange> Clone for NonceManager<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            tracker: self.tracker.clone(),
+            accounts: self.accounts.clone(),
+        }
+    }
+}
+
+/// Exclusive access to the nonce of an account.
+pub struct NonceGuard<T: Exchange> {
+    account: T::AccountId,
+    pending: MutexGuard<Pending<T>>,
+    nonce: T::Index,
+}
+
+impl<T: Exchange> NonceManager<T>
+where
+    T::AccountId: std::hash::Hash,
+{
+    /// Creates a new `NonceManager`.
+    pub fn new(client: Client<T>, tracker: Tracker<T>) -> Self {
+        Self {
+            client,
+            tracker,
+            accounts: Default::default(),
+        }
+    }
+
+    /// Locks `account` and reserves its next nonce.
+    ///
+    /// Other transfers from the account wait until the guard is dropped.
+    pub fn reserve(
+        &self,
+        account: T::AccountId,
+    ) -> impl Future<Item = NonceGuard<T>, Error = Error> {
+        let lock = self
+            .accounts
+            .lock()
+            .unwrap()
+            .entry(account.clone())
+            .or_insert_with(|| AsyncMutex::new(Pending::<T>::new()))
+            .clone();
+        let manager = self.clone();
+        lock.lock()
+            .map_err(|()| unreachable!("futures_locks::Mutex::lock never fails"))
+            .and_then(move |pending| {
+                let guard = NonceGuard {
+                    account,
+                    pending,
+                    nonce: Default::default(),
+                };
+                manager.resync(guard)
+            })
+    }
+
+    /// Recomputes the nonce of `guard` from the chain.
+    pub fn resync(
+        &self,
+        mut guard: NonceGuard<T>,
+    ) -> impl Future<Item = NonceGuard<T>, Error = Error> {
+        let tracker = self.tracker.clone();
+        self.client
+            .account_nonce(guard.account.clone())
+            .map_err(Error::from)
+            .map(move |account_nonce| {
+                let pending = &mut *guard.pending;
+                *pending = pending.split_off(&account_nonce);
+                let best: u64 = tracker.best_number().saturated_into();
+                pending.retain(|_, nonce_use| {
+                    match nonce_use {
+                        Use::Submitted(hash) => {
+                            tracker
+                                .status(hash)
+                                .map(|status| status.is_pending())
+                                .unwrap_or(false)
+                        }
+                        Use::Offline(death) => death.map(|death| best < death).unwrap_or(true),
+                    }
+                });
+                let mut nonce = account_nonce;
+                while pending.contains_key(&nonce) {
+                    nonce += One::one();
+                }
+                log::debug!("next nonce {:?} of {:?}", nonce, guard.account);
+                guard.nonce = nonce;
+                guard
+            })
+    }
+}
+
+impl<T: Exchange> NonceGuard<T> {
+    /// Returns the reserved nonce.
+    pub fn nonce(&self) -> T::Index {
+        self.nonce
+    }
+
+    /// Records the extrinsic `hash` submitted with the nonce `nonce`.
+    pub fn record(&mut self, nonce: T::Index, hash: T::Hash) {
+        self.pending.insert(nonce, Use::Submitted(hash));
+    }
+
+    /// Keeps the reserved nonce reserved for a payload signed offline which
+    /// is valid until the block `death`, and releases the account.
+    pub fn offline(mut self, death: Option<u64>) {
+        let nonce = self.nonce;
+        self.pending.insert(nonce, Use::Offline(death));
+    }
+
+    /// Records the extrinsic `hash` submitted with the reserved nonce and
+    /// releases the account.
+    pub fn submitted(mut self, hash: T::Hash) {
+        let nonce = self.nonce;
+        self.record(nonce, hash);
+    }
+}
@@ -0,0 +1,156 @@
+//! Offline signing of transfers.
+//!
+//! Transfers from cold wallets are split in three steps. The shim builds an
+//! `UnsignedTransfer` containing everything needed to sign, an offline
+//! machine holding the secret signs it and produces a `SignedTransfer`, and
+//! the shim verifies the signature and broadcasts the extrinsic.
+//!
+//! # File format
+//!
+//! Both files contain a single `0x` prefixed hex string of the SCALE encoding
+//! of the respective struct, with the fields encoded in declaration order:
+//!
+//! ```text
+//! UnsignedTransfer = version: u8 ++ signer: Public ++ call: Call ++
+//!     nonce: Index ++ tip: Balance ++ era: Era ++ genesis_hash: Hash ++
+//!     era_hash: Hash
+//! SignedTransfer = UnsignedTransfer ++ signature: Signature
+//! ```
+//!
+//! The signature covers the payload returned by
+//! `UnsignedTransfer::signing_payload`.
+use crate::{
+    extrinsic::{
+        self,
+        Extrinsic,
+    },
+    Error,
+    Exchange,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use sr_primitives::{
+    generic::Era,
+    traits::StaticLookup,
+};
+use substrate_primitives::crypto::Pair;
+
+/// Version of the offline signing file format.
+pub const VERSION: u8 = 2;
+
+/// A transfer waiting to be signed offline.
+#[derive(Encode, Decode)]
+pub struct UnsignedTransfer<T: Exchange> {
+    /// File format version.
+    pub version: u8,
+    /// Public key of the signing account.
+    pub signer: <T::Pair as Pair>::Public,
+    /// Transfer call.
+    pub call: T::Call,
+    /// Nonce of the signing account.
+    pub nonce: T::Index,
+    /// Tip paid to the block author.
+    pub tip: T::Balance,
+    /// Era the transaction is valid in.
+    pub era: Era,
+    /// Genesis hash of the chain.
+    pub genesis_hash: T::Hash,
+    /// Hash of the block the era starts at.
+    pub era_hash: T::Hash,
+}
+
+impl<T: Exchange> UnsignedTransfer<T> {
+    /// Decodes an unsigned transfer file.
+    pub fn from_hex(value: &str) -> Result<Self, Error> {
+        let transfer: Self = from_hex(value)?;
+        if transfer.version != VERSION {
+            return Err(Error::InvalidPayload)
+        }
+        Ok(transfer)
+    }
+
+    /// Returns the signed extensions of the transfer.
+    fn extra(&self) -> T::SignedExtra {
+        T::signed_extra(self.nonce, self.era, self.tip, self.genesis_hash, self.era_hash)
+    }
+
+    /// Returns the payload to sign.
+    pub fn signing_payload(&self) -> Result<Vec<u8>, Error> {
+        extrinsic::signing_payload::<T>(&self.call, &self.extra())
+    }
+
+    /// Signs the transfer with `pair`.
+    pub fn sign(self, pair: &T::Pair) -> Result<SignedTransfer<T>, Error> {
+        if pair.public() != self.signer {
+            return Err(Error::InvalidSignature)
+        }
+        let signature = pair.sign(&self.signing_payload()?);
+        Ok(SignedTransfer {
+            transfer: self,
+            signature,
+        })
+    }
+}
+
+/// A transfer signed offline.
+#[derive(Encode, Decode)]
+pub struct SignedTransfer<T: Exchange> {
+    /// The signed transfer.
+    pub transfer: UnsignedTransfer<T>,
+    /// Signature of the signing payload.
+    pub signature: <T::Pair as Pair>::Signature,
+}
+
+impl<T: Exchange> SignedTransfer<T>
+where
+    <T::Pair as Pair>::Public: Into<<T::Lookup as StaticLookup>::Source>,
+{
+    /// Decodes a signed transfer file.
+    pub fn from_hex(value: &str) -> Result<Self, Error> {
+        let signed: Self = from_hex(value)?;
+        if signed.transfer.version != VERSION {
+            return Err(Error::InvalidPayload)
+        }
+        Ok(signed)
+    }
+
+    /// Verifies the signature and returns the extrinsic.
+    pub fn into_extrinsic(self) -> Result<Extrinsic<T>, Error> {
+        let transfer = self.transfer;
+        let payload = transfer.signing_payload()?;
+        if !T::Pair::verify(&self.signature, &payload, &transfer.signer) {
+            return Err(Error::InvalidSignature)
+        }
+        let extra = transfer.extra();
+        Ok(extrinsic::signed::<T>(
+            transfer.call,
+            transfer.signer,
+            self.signature,
+            extra,
+        ))
+    }
+}
+
+/// Encodes `value` as a `0x` prefixed hex string.
+pub fn to_hex<E: Encode>(value: &E) -> String {
+    format!("0x{}", hex::encode(value.encode()))
+}
+
+/// Decodes a `0x` prefixed hex string.
+pub fn from_hex<D: Decode>(value: &str) -> Result<D, Error> {
+    let value = value.trim();
+    let value = if value.starts_with("0x") {
+        &value[2..]
+    } else {
+        value
+    };
+    let bytes = hex::decode(value).map_err(|_| Error::InvalidPayload)?;
+    let mut input = &bytes[..];
+    let decoded = D::decode(&mut input).map_err(|_| Error::InvalidPayload)?;
+    if !input.is_empty() {
+        return Err(Error::InvalidPayload)
+    }
+    Ok(decoded)
+}
@@ -0,0 +1,129 @@
+//! Persistent record of withdrawals.
+//!
+//! Every extrinsic is recorded together with its signer, nonce and encoded
+//! form before it is submitted. The tracker keeps the recorded state up to
+//! date, so withdrawals still pending when the shim stops can be rebroadcast
+//! or resolved on the next start.
+use crate::{
+    db::{
+        Batch,
+        Db,
+        Tree,
+    },
+    tracker::{
+        Inclusion,
+        TransactionStatus,
+    },
+    Error,
+    Exchange,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use sr_primitives::generic::Era;
+use substrate_subxt::srml::balances::Balances;
+
+/// Name of the outbox tree.
+const TREE: &str = "outbox";
+
+/// Layout version of the recorded withdrawals.
+const VERSION: u8 = 1;
+
+/// A recorded withdrawal.
+#[derive(Clone, Encode, Decode)]
+pub struct Withdrawal<T: Exchange> {
+    /// Client supplied idempotency key.
+    pub request_id: Option<String>,
+    /// Signing account.
+    pub signer: T::AccountId,
+    /// Nonce of the signing account.
+    pub nonce: T::Index,
+    /// Transfer call.
+    pub call: T::Call,
+    /// Era the extrinsic is valid in.
+    pub era: Era,
+    /// Hash of the block the era starts at.
+    pub era_hash: T::Hash,
+    /// Tip paid to the block author.
+    pub tip: <T as Balances>::Balance,
+    /// The encoded signed extrinsic.
+    pub extrinsic: Vec<u8>,
+    /// Best block number when the extrinsic was submitted.
+    pub submitted_at: T::BlockNumber,
+    /// Block including the extrinsic.
+    pub inclusion: Option<Inclusion<T>>,
+    /// Latest known status.
+    pub status: TransactionStatus,
+}
+
+/// Persistent record of withdrawals by extrinsic hash.
+pub struct Outbox<T: Exchange> {
+    withdrawals: Tree<Withdrawal<T>>,
+}
+
+impl<T: Exchange> Clone for Outbox<T> {
+    fn clone(&self) -> Self {
+        Self {
+            withdrawals: self.withdrawals.clone(),
+        }
+    }
+}
+
+impl<T: Exchange> Outbox<T> {
+    /// Opens the outbox in `db`.
+    pub fn new(db: &Db) -> Result<Self, Error> {
+        let withdrawals = db.tree(TREE)?;
+        match db.version(TREE)? {
+            Some(VERSION) => {}
+            None if withdrawals.is_empty() => {
+                let mut batch = Batch::default();
+                db.set_version(&mut batch, TREE, VERSION)?;
+                db.apply(batch)?;
+            }
+            _ => return Err(Error::CorruptedDatabase),
+        }
+        Ok(Self { withdrawals })
+    }
+
+    /// Records a withdrawal about to be submitted in `batch`.
+    pub fn insert(&self, batch: &mut Batch, hash: &T::Hash, withdrawal: &Withdrawal<T>) {
+        batch.insert(&self.withdrawals, hash, withdrawal);
+    }
+
+    /// Returns the withdrawal with the extrinsic `hash`.
+    pub fn get(&self, hash: &T::Hash) -> Result<Option<Withdrawal<T>>, Error> {
+        self.withdrawals.get(hash)
+    }
+
+    /// Updates the status of the withdrawal with the extrinsic `hash`.
+    pub fn update(
+        &self,
+        hash: &T::Hash,
+        status: &TransactionStatus,
+        inclusion: &Option<Inclusion<T>>,
+    ) -> Result<(), Error> {
+        if let Some(mut withdrawal) = self.withdrawals.get(hash)? {
+            withdrawal.status = status.clone();
+            withdrawal.inclusion = inclusion.clone();
+            self.withdrawals.insert(hash, &withdrawal)?;
+            self.withdrawals.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Returns all withdrawals which are not final yet.
+    pub fn pending(&self) -> Result<Vec<(T::Hash, Withdrawal<T>)>, Error> {
+        let mut pending = Vec::new();
+        for entry in self.withdrawals.iter() {
+            let (key, withdrawal) = entry?;
+            if withdrawal.status.is_pending() {
+                let hash = Decode::decode(&mut &key[..])
+                    .map_err(|_| Error::CorruptedDatabase)?;
+                pending.push((hash, withdrawal));
+            }
+        }
+        Ok(pending)
+    }
+}
+
@@ -0,0 +1,480 @@
+//! Subscriptions to balance changes and deposits.
+//!
+//! Subscriptions are served over the WebSocket transport. A balance
+//! subscription sends the balance of an account at the next best block and
+//! whenever it changes. A deposit subscription sends every deposit found to a
+//! watched address, or to one of them. The number of subscriptions of a
+//! connection and the total number of balance subscriptions are limited.
+//! Notifications are queued to the connection without waiting for the
+//! client, a subscription whose connection doesn't keep up is dropped.
+//! Subscriptions end when they are cancelled or the connection closes.
+use crate::{
+    amount::{
+        Amount,
+        Denomination,
+    },
+    balance::{
+        self,
+        AccountBalance,
+    },
+    indexer::{
+        Deposit,
+        Indexer,
+    },
+    tracker::RETRY_DELAY,
+    Error,
+    Exchange,
+};
+use futures::{
+    future::{
+        self,
+        Future,
+        Loop,
+    },
+    stream::{
+        self,
+        Stream,
+    },
+    AsyncSink,
+    Sink as _,
+};
+use jsonrpc_core::{
+    Error as RpcError,
+    Metadata,
+};
+use jsonrpc_derive::rpc;
+use jsonrpc_pubsub::{
+    typed::{
+        Sink,
+        Subscriber,
+    },
+    PubSubMetadata,
+    Session,
+    SubscriptionId,
+};
+use serde::Serialize;
+use sr_primitives::traits::Header;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Instant,
+};
+use substrate_primitives::crypto::{
+    Pair,
+    Ss58Codec,
+};
+use substrate_subxt::{
+    srml::system::System,
+    Client,
+};
+use tokio::timer::Delay;
+
+/// Metadata of a WebSocket connection.
+#[derive(Clone, Default)]
+pub struct Meta {
+    /// Pub/sub session of the connection.
+    pub session: Option<Arc<Session>>,
+    /// Id of the connection.
+    pub connection: u64,
+}
+
+impl Metadata for Meta {}
+
+impl PubSubMetadata for Meta {
+    fn session(&self) -> Option<Arc<Session>> {
+        self.session.clone()
+    }
+}
+
+/// Pub/sub methods of the shim.
+#[rpc]
+pub trait PubSub {
+    /// Connection metadata.
+    type Metadata;
+
+    /// Subscribe to the balance of an account.
+    ///
+    /// Sends the balance at the next best block and whenever it changes.
+    #[pubsub(subscription = "account_balance", subscribe, name = "subscribe_account_balance")]
+    fn subscribe_account_balance(
+        &self,
+        meta: Self::Metadata,
+        subscriber: Subscriber<AccountBalance>,
+        address: String,
+    );
+
+    /// Cancel a balance subscription.
+    #[pubsub(
+        subscription = "account_balance",
+        unsubscribe,
+        name = "unsubscribe_account_balance"
+    )]
+    fn unsubscribe_account_balance(
+        &self,
+        meta: Option<Self::Metadata>,
+        id: SubscriptionId,
+    ) -> Result<bool, RpcError>;
+
+    /// Subscribe to the deposits found to the watched addresses, or only to
+    /// `address`.
+    #[pubsub(subscription = "deposit", subscribe, name = "subscribe_deposits")]
+    fn subscribe_deposits(
+        &self,
+        meta: Self::Metadata,
+        subscriber: Subscriber<Deposit>,
+        address: Option<String>,
+    );
+
+    /// Cancel a deposit subscription.
+    #[pubsub(subscription = "deposit", unsubscribe, name = "unsubscribe_deposits")]
+    fn unsubscribe_deposits(
+        &self,
+        meta: Option<Self::Metadata>,
+        id: SubscriptionId,
+    ) -> Result<bool, RpcError>;
+}
+
+/// Parts of a balance whose change is reported.
+type Snapshot = (Amount, Amount, Amount);
+
+struct BalanceSubscription<T: Exchange> {
+    connection: u64,
+    account: T::AccountId,
+    last: Option<Snapshot>,
+    sink: Sink<AccountBalance>,
+}
+
+struct DepositSubscription {
+    connection: u64,
+    address: Option<String>,
+    sink: Sink<Deposit>,
+}
+
+struct State<T: Exchange> {
+    next_id: u64,
+    balances: HashMap<u64, BalanceSubscription<T>>,
+    deposits: HashMap<u64, DepositSubscription>,
+    /// Connections which remove their subscriptions when they close.
+    connections: HashSet<u64>,
+}
+
+impl<T: Exchange> Default for State<T> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            balances: HashMap::new(),
+            deposits: HashMap::new(),
+            connections: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Exchange> State<T> {
+    /// Returns the number of subscriptions of `connection`.
+    fn count(&self, connection: u64) -> usize {
+        let balances = self.balances.values().filter(|sub| sub.connection == connection);
+        let deposits = self.deposits.values().filter(|sub| sub.connection == connection);
+        balances.count() + deposits.count()
+    }
+
+    /// Removes the subscriptions of `connection`.
+    fn close(&mut self, connection: u64) {
+        self.balances.retain(|_, sub| sub.connection != connection);
+        self.deposits.retain(|_, sub| sub.connection != connection);
+        self.connections.remove(&connection);
+    }
+}
+
+/// Returns `true` if `meta` belongs to the connection `connection`.
+fn owns(meta: &Option<Meta>, connection: u64) -> bool {
+    meta.as_ref().map(|meta| meta.connection == connection).unwrap_or(false)
+}
+
+/// Queues `value` to `sink` without waiting for the client, returns `false`
+/// if the connection closed or its buffer is full.
+fn send<V: Serialize>(sink: &mut Sink<V>, value: V) -> bool {
+    match sink.start_send(Ok(value)) {
+        Ok(AsyncSink::Ready) => true,
+        Ok(AsyncSink::NotReady(_)) | Err(_) => false,
+    }
+}
+
+/// Balance and deposit subscriptions.
+pub struct Subscriptions<T: Exchange> {
+    client: Client<T>,
+    indexer: Indexer<T>,
+    denomination: Denomination,
+    limit: usize,
+    balance_limit: usize,
+    state: Arc<Mutex<State<T>>>,
+}
+
+impl<T: Exchange> Clone for Subscriptions<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            indexer: self.indexer.clone(),
+            denomination: self.denomination.clone(),
+            limit: self.limit,
+            balance_limit: self.balance_limit,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T: Exchange> Subscriptions<T>
+where
+    <T as System>::AccountId: std::hash::Hash + Ss58Codec,
+{
+    /// Creates a new `Subscriptions` allowing `limit` subscriptions per
+    /// connection and `balance_limit` balance subscriptions in total.
+    pub fn new(
+        client: Client<T>,
+        indexer: Indexer<T>,
+        denomination: Denomination,
+        limit: usize,
+        balance_limit: usize,
+    ) -> Self {
+        Self {
+            client,
+            indexer,
+            denomination,
+            limit,
+            balance_limit,
+            state: Default::default(),
+        }
+    }
+
+    /// Assigns an id to `subscriber` and adds the subscription built by
+    /// `insert`, if the connection is below the limit and, for a `balance`
+    /// subscription, the balance subscriptions are below theirs.
+    fn add<V, F>(&self, meta: &Meta, subscriber: Subscriber<V>, balance: bool, insert: F)
+    where
+        F: FnOnce(&mut State<T>, u64, Sink<V>),
+    {
+        let mut state = self.state.lock().unwrap();
+        if state.count(meta.connection) >= self.limit {
+            subscriber.reject(Error::TooManySubscriptions.into()).ok();
+            return
+        }
+        if balance && state.balances.len() >= self.balance_limit {
+            subscriber.reject(Error::TooManyBalanceSubscriptions.into()).ok();
+            return
+        }
+        if let Some(session) = &meta.session {
+            if state.connections.insert(meta.connection) {
+                let subscriptions = self.state.clone();
+                let connection = meta.connection;
+                session.on_drop(move || subscriptions.lock().unwrap().close(connection));
+            }
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        if let Ok(sink) = subscriber.assign_id(SubscriptionId::Number(id)) {
+            insert(&mut state, id, sink);
+        }
+    }
+
+    /// Sends the balances which changed in the best block `header`.
+    fn process_best(&self, header: T::Header) -> impl Future<Item = (), Error = Error> {
+        let accounts: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .balances
+            .iter()
+            .map(|(id, sub)| (*id, sub.account.clone()))
+            .collect();
+        let subscriptions = self.clone();
+        let hash = header.hash();
+        let number = *header.number();
+        stream::iter_ok(accounts).for_each(move |(id, account)| {
+            let subscriptions = subscriptions.clone();
+            let balance = balance::account_balance(
+                &subscriptions.client,
+                &subscriptions.denomination,
+                &account,
+                hash,
+                number,
+            );
+            balance.and_then(move |balance| {
+                let snapshot = (
+                    balance.free.clone(),
+                    balance.reserved.clone(),
+                    balance.transferable.clone(),
+                );
+                let mut state = subscriptions.state.lock().unwrap();
+                let sent = match state.balances.get_mut(&id) {
+                    Some(sub) if sub.last.as_ref() != Some(&snapshot) => {
+                        sub.last = Some(snapshot);
+                        send(&mut sub.sink, balance)
+                    }
+                    _ => true,
+                };
+                if !sent {
+                    state.balances.remove(&id);
+                }
+                Ok(())
+            })
+        })
+    }
+
+    /// Sends a new `deposit` to the matching deposit subscriptions.
+    fn process_deposit(&self, deposit: Deposit) {
+        self.state.lock().unwrap().deposits.retain(|_, sub| {
+            match &sub.address {
+                Some(address) if *address != deposit.address => true,
+                _ => send(&mut sub.sink, deposit.clone()),
+            }
+        });
+    }
+
+    /// Follows the chain and the indexer and notifies the subscribers.
+    ///
+    /// Subscribes to the best blocks again after `RETRY_DELAY` if the
+    /// subscription fails.
+    pub fn run(self) -> impl Future<Item = (), Error = ()> {
+        let balances = future::loop_fn(self.clone(), |subscriptions| {
+            subscriptions.follow().then(move |result| {
+                match result {
+                    Ok(()) => log::warn!("balance subscription ended, resubscribing"),
+                    Err(err) => log::error!("balance subscription failed: {:?}", err),
+                }
+                Delay::new(Instant::now() + RETRY_DELAY)
+                    .then(move |_| Ok::<_, ()>(Loop::<(), _>::Continue(subscriptions)))
+            })
+        });
+        let subscriptions = self.clone();
+        let deposits = self
+            .indexer
+            .subscribe()
+            .for_each(move |deposit| {
+                subscriptions.process_deposit(deposit);
+                Ok(())
+            });
+        balances.join(deposits).map(|_| ())
+    }
+
+    fn follow(&self) -> impl Future<Item = (), Error = Error> {
+        let subscriptions = self.clone();
+        self.client
+            .subscribe_blocks()
+            .map_err(Error::from)
+            .and_then(move |best| {
+                best.map_err(Error::from).for_each(move |header| {
+                    subscriptions.process_best(header).or_else(|err| {
+                        log::warn!("failed to query subscribed balances: {:?}", err);
+                        Ok(())
+                    })
+                })
+            })
+    }
+}
+
+impl<T: Exchange> PubSub for Subscriptions<T>
+where
+    <T as System>::AccountId: std::hash::Hash + Ss58Codec,
+    <T::Pair as Pair>::Public: Ss58Codec + Into<<T as System>::AccountId>,
+{
+    type Metadata = Meta;
+
+    fn subscribe_account_balance(
+        &self,
+        meta: Meta,
+        subscriber: Subscriber<AccountBalance>,
+        address: String,
+    ) {
+        let account: T::AccountId = match <T::Pair as Pair>::Public::from_string(&address) {
+            Ok(public) => public.into(),
+            Err(_) => {
+                subscriber.reject(Error::InvalidSS58.into()).ok();
+                return
+            }
+        };
+        let connection = meta.connection;
+        self.add(&meta, subscriber, true, move |state, id, sink| {
+            let sub = BalanceSubscription {
+                connection,
+                account,
+                last: None,
+                sink,
+            };
+            state.balances.insert(id, sub);
+        });
+    }
+
+    fn unsubscribe_account_balance(
+        &self,
+        meta: Option<Meta>,
+        id: SubscriptionId,
+    ) -> Result<bool, RpcError> {
+        let mut state = self.state.lock().unwrap();
+        match id {
+            SubscriptionId::Number(id) => {
+                match state.balances.get(&id) {
+                    Some(sub) if owns(&meta, sub.connection) => {
+                        state.balances.remove(&id);
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            }
+            SubscriptionId::String(_) => Ok(false),
+        }
+    }
+
+    fn subscribe_deposits(
+        &self,
+        meta: Meta,
+        subscriber: Subscriber<Deposit>,
+        address: Option<String>,
+    ) {
+        let address = match address {
+            Some(address) => {
+                match <T::Pair as Pair>::Public::from_string(&address) {
+                    Ok(public) => Some(public.to_ss58check()),
+                    Err(_) => {
+                        subscriber.reject(Error::InvalidSS58.into()).ok();
+                        return
+                    }
+                }
+            }
+            None => None,
+        };
+        let connection = meta.connection;
+        self.add(&meta, subscriber, false, move |state, id, sink| {
+            let sub = DepositSubscription {
+                connection,
+                address,
+                sink,
+            };
+            state.deposits.insert(id, sub);
+        });
+    }
+
+    fn unsubscribe_deposits(
+        &self,
+        meta: Option<Meta>,
+        id: SubscriptionId,
+    ) -> Result<bool, RpcError> {
+        let mut state = self.state.lock().unwrap();
+        match id {
+            SubscriptionId::Number(id) => {
+                match state.deposits.get(&id) {
+                    Some(sub) if owns(&meta, sub.connection) => {
+                        state.deposits.remove(&id);
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            }
+            SubscriptionId::String(_) => Ok(false),
+        }
+    }
+}
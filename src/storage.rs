@@ -0,0 +1,63 @@
+//! Raw storage queries.
+use crate::{
+    Error,
+    Exchange,
+};
+use futures::future::Future;
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use srml_system::EventRecord;
+use substrate_primitives::{
+    blake2_256,
+    storage::StorageKey,
+    twox_128,
+};
+use substrate_subxt::{
+    srml::system::System,
+    Client,
+};
+
+/// Returns the storage key of the events of a block.
+pub fn events_key() -> StorageKey {
+    StorageKey(twox_128(b"System Events").to_vec())
+}
+
+/// Returns the storage key of the entry `key` of the map `prefix` hashed with
+/// blake2_256.
+pub fn map_key<K: Encode>(prefix: &[u8], key: &K) -> StorageKey {
+    let mut bytes = prefix.to_vec();
+    key.encode_to(&mut bytes);
+    StorageKey(blake2_256(&bytes).to_vec())
+}
+
+/// Fetches the value stored under `key` in the block `block_hash`.
+pub fn fetch<T: Exchange, V: Decode>(
+    client: &Client<T>,
+    key: StorageKey,
+    block_hash: T::Hash,
+) -> impl Future<Item = Option<V>, Error = Error> {
+    client.fetch(key, Some(block_hash)).map_err(Into::into)
+}
+
+/// Fetches the events emitted in the block `block_hash`.
+pub fn events<T: Exchange>(
+    client: &Client<T>,
+    block_hash: T::Hash,
+) -> impl Future<Item = Vec<EventRecord<T::Event, T::Hash>>, Error = Error> {
+    client
+        .fetch(events_key(), Some(block_hash))
+        .map(Option::unwrap_or_default)
+        .map_err(Into::into)
+}
+
+/// Fetches the nonce of `account` in the block `block_hash`.
+pub fn account_nonce<T: Exchange>(
+    client: &Client<T>,
+    account: &<T as System>::AccountId,
+    block_hash: T::Hash,
+) -> impl Future<Item = T::Index, Error = Error> {
+    fetch::<T, T::Index>(client, map_key(b"System AccountNonce", account), block_hash)
+        .map(Option::unwrap_or_default)
+}
@@ -0,0 +1,867 @@
+//! Tracking of submitted extrinsics through the best and finalized blocks of
+//! the node.
+use crate::{
+    extrinsic,
+    metadata::Metadata,
+    outbox::{
+        Outbox,
+        Withdrawal,
+    },
+    storage,
+    transfer::BlockInfo,
+    webhook::{
+        Event,
+        Webhooks,
+    },
+    Error,
+    Exchange,
+};
+use futures::{
+    future::{
+        self,
+        Either,
+        Future,
+        Loop,
+    },
+    stream::{
+        self,
+        Stream,
+    },
+    sync::oneshot,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sr_primitives::traits::{
+    Header,
+    SaturatedConversion,
+    Zero,
+};
+use srml_system::Phase;
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use substrate_subxt::{
+    srml::system::System,
+    Client,
+};
+use tokio::timer::{
+    Delay,
+    Timeout,
+};
+
+/// Time a waiting transfer resolves with the current status at the latest.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(300);
+/// Delay before subscribing again after a subscription failed.
+pub(crate) const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Point in the life of an extrinsic to wait for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wait {
+    /// Return once the extrinsic was accepted by the transaction pool.
+    ///
+    /// Named after the state of the transaction pool, the extrinsic is
+    /// `broadcast` by then.
+    Ready,
+    /// Return once the extrinsic was included in a best block.
+    InBlock,
+    /// Return once the block including the extrinsic was finalized.
+    Finalized,
+}
+
+impl Default for Wait {
+    fn default() -> Self {
+        Wait::Ready
+    }
+}
+
+/// Block an extrinsic was included in.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Inclusion<T: Exchange> {
+    /// Hash of the block.
+    pub block_hash: T::Hash,
+    /// Number of the block.
+    pub block_number: T::BlockNumber,
+    /// Index of the extrinsic in the block.
+    pub index: u32,
+    /// Whether the block is finalized.
+    pub finalized: bool,
+}
+
+/// State of a submitted extrinsic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxState {
+    /// Signed and recorded, not yet accepted by the transaction pool.
+    Ready,
+    /// Accepted by the transaction pool, which gossips it to the peers of the
+    /// node.
+    Broadcast,
+    /// Included in a best block.
+    InBlock,
+    /// Included in a finalized block.
+    Finalized,
+    /// Removed from the transaction pool without being included.
+    Dropped,
+    /// Rejected by the transaction pool.
+    Invalid,
+    /// The era of the extrinsic ended in a finalized block before the nonce
+    /// of its signer reached its nonce.
+    Expired,
+}
+
+/// Error an extrinsic failed with at dispatch.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, Serialize)]
+pub struct DispatchFailure {
+    /// Index of the module raising the error, `None` if no module raised it.
+    pub module_index: Option<u8>,
+    /// Index of the error in the errors of the module.
+    pub error_index: u8,
+    /// Name of the module, if known to the runtime metadata.
+    pub module: Option<String>,
+    /// Name of the error, if known to the runtime metadata.
+    pub error: Option<String>,
+}
+
+/// Outcome of dispatching an included extrinsic.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The extrinsic emitted `ExtrinsicSuccess`.
+    Success,
+    /// The extrinsic emitted `ExtrinsicFailed` with the given error.
+    Failed(DispatchFailure),
+}
+
+/// Status of a submitted extrinsic.
+#[derive(Clone, Debug, Encode, Decode, Serialize)]
+pub struct TransactionStatus {
+    /// Hash of the extrinsic.
+    pub hash: String,
+    /// Current state.
+    pub state: TxState,
+    /// First block the extrinsic is no longer valid in, `None` if it is
+    /// immortal.
+    pub death: Option<u64>,
+    /// Block including the extrinsic.
+    pub block: Option<BlockInfo>,
+    /// Dispatch outcome, once included.
+    pub outcome: Option<Outcome>,
+}
+
+struct Tracked<T: Exchange> {
+    signer: T::AccountId,
+    nonce: T::Index,
+    /// First block the extrinsic may be included in.
+    from: T::BlockNumber,
+    inclusion: Option<Inclusion<T>>,
+    status: TransactionStatus,
+    waiters: Vec<(Wait, oneshot::Sender<TransactionStatus>)>,
+}
+
+impl TxState {
+    /// Returns `true` if the extrinsic waits for inclusion.
+    pub fn is_in_pool(self) -> bool {
+        match self {
+            TxState::Ready | TxState::Broadcast => true,
+            _ => false,
+        }
+    }
+}
+
+impl TransactionStatus {
+    /// Returns `true` if the state of the extrinsic can still change.
+    pub fn is_pending(&self) -> bool {
+        self.state.is_in_pool() || self.state == TxState::InBlock
+    }
+
+    /// Returns `true` if the extrinsic reached the point `wait` or can't reach
+    /// it any more.
+    pub fn reached(&self, wait: Wait) -> bool {
+        match (wait, self.state) {
+            (_, TxState::Dropped) | (_, TxState::Invalid) | (_, TxState::Expired) => {
+                true
+            }
+            (_, TxState::Ready) => false,
+            (Wait::Ready, _) => true,
+            (Wait::InBlock, TxState::InBlock) => true,
+            (_, TxState::Finalized) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: Exchange> Tracked<T> {
+    fn notify(&mut self) {
+        let status = self.status.clone();
+        let waiters = std::mem::replace(&mut self.waiters, Vec::new());
+        for (wait, waiter) in waiters {
+            if waiter.is_canceled() {
+                continue
+            }
+            if status.reached(wait) {
+                waiter.send(status.clone()).ok();
+            } else {
+                self.waiters.push((wait, waiter));
+            }
+        }
+    }
+}
+
+/// A new head of the chain.
+pub enum Head<H> {
+    /// New best block.
+    Best(H),
+    /// New finalized block.
+    Finalized(H),
+}
+
+/// Tracks the state of submitted extrinsics.
+pub struct Tracker<T: Exchange> {
+    client: Client<T>,
+    metadata: Metadata<T>,
+    outbox: Outbox<T>,
+    webhooks: Webhooks,
+    best: Arc<Mutex<T::BlockNumber>>,
+    inspected: Arc<Mutex<BTreeMap<T::BlockNumber, T::Hash>>>,
+    txs: Arc<Mutex<HashMap<T::Hash, Tracked<T>>>>,
+}
+
+impl<T: Exchange> Clone for Tracker<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            metadata: self.metadata.clone(),
+            outbox: self.outbox.clone(),
+            webhooks: self.webhooks.clone(),
+            best: self.best.clone(),
+            inspected: self.inspected.clone(),
+            txs: self.txs.clone(),
+        }
+    }
+}
+
+/// Extrinsics found in a block with their dispatch outcome.
+type Found<T> = Vec<(<T as System>::Hash, Inclusion<T>, Option<Outcome>)>;
+
+impl<T: Exchange> Tracker<T>
+where
+    T::AccountId: std::hash::Hash,
+{
+    /// Creates a new `Tracker` persisting state changes to `outbox` and
+    /// reporting them to `webhooks`, resolving dispatch errors with
+    /// `metadata`.
+    pub fn new(
+        client: Client<T>,
+        metadata: Metadata<T>,
+        outbox: Outbox<T>,
+        webhooks: Webhooks,
+    ) -> Self {
+        Self {
+            client,
+            metadata,
+            outbox,
+            webhooks,
+            best: Default::default(),
+            inspected: Default::default(),
+            txs: Default::default(),
+        }
+    }
+
+    /// Returns the number of the latest best block seen.
+    pub fn best_number(&self) -> T::BlockNumber {
+        *self.best.lock().unwrap()
+    }
+
+    /// Sets the number of the latest best block.
+    pub fn set_best_number(&self, number: T::BlockNumber) {
+        let mut best = self.best.lock().unwrap();
+        if number > *best {
+            *best = number;
+        }
+    }
+
+    /// Returns the status of a new extrinsic valid until `death`.
+    pub fn new_status(hash: &T::Hash, death: Option<u64>) -> TransactionStatus {
+        TransactionStatus {
+            hash: format!("{:?}", hash),
+            state: TxState::Ready,
+            death,
+            block: None,
+            outcome: None,
+        }
+    }
+
+    fn persist(&self, hash: &T::Hash, tracked: &mut Tracked<T>) {
+        tracked.notify();
+        if let Err(err) = self.outbox.update(hash, &tracked.status, &tracked.inclusion) {
+            log::error!("failed to persist status of {:?}: {:?}", hash, err);
+        }
+    }
+
+    /// Reports the new state of an extrinsic to the webhooks.
+    fn report(&self, status: &TransactionStatus) {
+        let event = match (status.state, &status.outcome) {
+            (TxState::Ready, _) | (TxState::Broadcast, _) => return,
+            (TxState::InBlock, Some(Outcome::Failed(_)))
+            | (TxState::Finalized, Some(Outcome::Failed(_))) => {
+                Event::WithdrawalFailed(status.clone())
+            }
+            (TxState::InBlock, _) => Event::WithdrawalIncluded(status.clone()),
+            (TxState::Finalized, _) => Event::WithdrawalFinalized(status.clone()),
+            (TxState::Dropped, _) | (TxState::Invalid, _) | (TxState::Expired, _) => {
+                Event::WithdrawalFailed(status.clone())
+            }
+        };
+        if let Err(err) = self.webhooks.notify(event) {
+            log::error!("failed to queue notification for {}: {:?}", status.hash, err);
+        }
+    }
+
+    /// Applies `f` to the extrinsic `hash` and stops tracking it once its
+    /// state is final.
+    fn update<F: FnOnce(&mut Tracked<T>)>(&self, hash: &T::Hash, f: F) {
+        let mut txs = self.txs.lock().unwrap();
+        let done = match txs.get_mut(hash) {
+            Some(tracked) => {
+                let state = tracked.status.state;
+                f(tracked);
+                self.persist(hash, tracked);
+                if tracked.status.state != state {
+                    self.report(&tracked.status);
+                }
+                !tracked.status.is_pending()
+            }
+            None => false,
+        };
+        if done {
+            txs.remove(hash);
+        }
+    }
+
+    /// Starts tracking an extrinsic submitted to the transaction pool.
+    ///
+    /// Must be called before submitting so the including block can't be
+    /// missed.
+    pub fn submitted(
+        &self,
+        hash: T::Hash,
+        signer: T::AccountId,
+        nonce: T::Index,
+        from: T::BlockNumber,
+        death: Option<u64>,
+    ) {
+        let tracked = Tracked {
+            signer,
+            nonce,
+            from,
+            inclusion: None,
+            status: Self::new_status(&hash, death),
+            waiters: Vec::new(),
+        };
+        self.txs.lock().unwrap().insert(hash, tracked);
+    }
+
+    /// Resumes tracking a withdrawal recorded in the outbox.
+    pub fn restore(&self, hash: T::Hash, withdrawal: &Withdrawal<T>) {
+        let tracked = Tracked {
+            signer: withdrawal.signer.clone(),
+            nonce: withdrawal.nonce,
+            from: withdrawal.submitted_at,
+            inclusion: withdrawal.inclusion.clone(),
+            status: withdrawal.status.clone(),
+            waiters: Vec::new(),
+        };
+        self.txs.lock().unwrap().insert(hash, tracked);
+    }
+
+    /// Marks an extrinsic as accepted by the transaction pool.
+    pub fn broadcast(&self, hash: &T::Hash) {
+        self.update(hash, |tracked| {
+            if tracked.status.state == TxState::Ready {
+                tracked.status.state = TxState::Broadcast;
+            }
+        });
+    }
+
+    /// Marks an extrinsic as rejected by the transaction pool.
+    pub fn invalid(&self, hash: &T::Hash) {
+        self.update(hash, |tracked| tracked.status.state = TxState::Invalid);
+    }
+
+    /// Resolves once the extrinsic `hash` reached the point `wait`.
+    ///
+    /// Resolves with the current status if the extrinsic doesn't reach `wait`
+    /// within `WAIT_TIMEOUT`.
+    pub fn wait(
+        &self,
+        hash: &T::Hash,
+        wait: Wait,
+    ) -> impl Future<Item = TransactionStatus, Error = Error> {
+        let (sender, receiver) = oneshot::channel();
+        let tracked = match self.txs.lock().unwrap().get_mut(hash) {
+            Some(tracked) => {
+                tracked.waiters.push((wait, sender));
+                tracked.notify();
+                true
+            }
+            None => false,
+        };
+        if !tracked {
+            // Extrinsics in a final state are only kept in the outbox.
+            let status = self.status(hash).ok_or(Error::UnknownTransaction);
+            return Either::A(future::result(status))
+        }
+        let tracker = self.clone();
+        let hash = *hash;
+        let wait = Timeout::new(receiver, WAIT_TIMEOUT).then(move |status| {
+            match status {
+                Ok(status) => Ok(status),
+                Err(ref err) if err.is_elapsed() => {
+                    tracker.status(&hash).ok_or(Error::UnknownTransaction)
+                }
+                Err(_) => Err(Error::UnknownTransaction),
+            }
+        });
+        Either::B(wait)
+    }
+
+    /// Returns the status of the extrinsic `hash`.
+    ///
+    /// Falls back to the outbox for extrinsics which are no longer tracked.
+    pub fn status(&self, hash: &T::Hash) -> Option<TransactionStatus> {
+        if let Some(tracked) = self.txs.lock().unwrap().get(hash) {
+            return Some(tracked.status.clone())
+        }
+        self.outbox
+            .get(hash)
+            .ok()
+            .and_then(|withdrawal| withdrawal.map(|withdrawal| withdrawal.status))
+    }
+
+    fn is_pending(&self, hash: &T::Hash) -> bool {
+        self.txs
+            .lock()
+            .unwrap()
+            .get(hash)
+            .map(|tracked| tracked.status.is_pending())
+            .unwrap_or(false)
+    }
+
+    /// Marks an extrinsic as included in a block.
+    pub fn included(&self, hash: &T::Hash, inclusion: Inclusion<T>, outcome: Option<Outcome>) {
+        log::info!("{:?} included in {:?}", hash, inclusion.block_hash);
+        self.update(hash, |tracked| {
+            tracked.status.state = TxState::InBlock;
+            tracked.status.block = Some(inclusion.clone().into());
+            tracked.status.outcome = outcome;
+            tracked.inclusion = Some(inclusion);
+        });
+    }
+
+    fn finalized(&self, hash: &T::Hash) {
+        self.update(hash, |tracked| {
+            if let Some(inclusion) = &mut tracked.inclusion {
+                log::info!("{:?} finalized in {:?}", hash, inclusion.block_hash);
+                inclusion.finalized = true;
+                tracked.status.state = TxState::Finalized;
+                tracked.status.block = Some(inclusion.clone().into());
+            }
+        });
+    }
+
+    /// Marks an extrinsic as no longer included in a block.
+    pub fn retracted(&self, hash: &T::Hash) {
+        log::warn!("{:?} retracted", hash);
+        self.update(hash, |tracked| {
+            tracked.status.state = TxState::Broadcast;
+            tracked.status.block = None;
+            tracked.status.outcome = None;
+            tracked.inclusion = None;
+        });
+    }
+
+    /// Marks an extrinsic as dropped from the transaction pool.
+    pub fn dropped(&self, hash: &T::Hash) {
+        log::warn!("{:?} dropped", hash);
+        self.update(hash, |tracked| tracked.status.state = TxState::Dropped);
+    }
+
+    /// Marks an extrinsic as expired.
+    pub fn expired(&self, hash: &T::Hash) {
+        log::warn!("{:?} expired", hash);
+        self.update(hash, |tracked| tracked.status.state = TxState::Expired);
+    }
+
+    fn dropped_before(&self, signer: &T::AccountId, account_nonce: T::Index) {
+        let dropped: Vec<_> = self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, tracked)| {
+                &tracked.signer == signer
+                    && tracked.status.state.is_in_pool()
+                    && tracked.nonce < account_nonce
+            })
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in dropped {
+            self.dropped(&hash);
+        }
+    }
+
+    /// Returns the tracked extrinsics in the block `block_hash` with their
+    /// dispatch outcome.
+    fn inspect_block<F>(
+        &self,
+        block_hash: T::Hash,
+        block_number: T::BlockNumber,
+        filter: F,
+    ) -> impl Future<Item = Found<T>, Error = Error>
+    where
+        F: Fn(&T::Hash) -> bool + Send + 'static,
+    {
+        let client = self.client.clone();
+        let metadata = self.metadata.clone();
+        self.client
+            .block(Some(block_hash))
+            .map_err(Error::from)
+            .and_then(move |block| {
+                let found: Vec<_> = block
+                    .into_iter()
+                    .flat_map(|block| block.block.extrinsics)
+                    .enumerate()
+                    .filter_map(|(index, xt)| {
+                        let hash = extrinsic::hash::<T, _>(&xt);
+                        if filter(&hash) {
+                            Some((hash, index as u32))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                if found.is_empty() {
+                    return Either::A(future::ok(Vec::new()))
+                }
+                let events = storage::events(&client, block_hash).and_then(move |events| {
+                    let found = found.into_iter().map(move |(hash, index)| {
+                        let outcome = events
+                            .iter()
+                            .filter(|record| record.phase == Phase::ApplyExtrinsic(index))
+                            .filter_map(|record| T::extrinsic_outcome(&record.event))
+                            .next();
+                        let inclusion = Inclusion {
+                            block_hash,
+                            block_number,
+                            index,
+                            finalized: false,
+                        };
+                        let outcome = match outcome {
+                            Some(Outcome::Failed(failure)) => {
+                                let failure = metadata.resolve(failure, block_hash);
+                                Either::A(failure.map(|failure| Some(Outcome::Failed(failure))))
+                            }
+                            outcome => Either::B(future::ok(outcome)),
+                        };
+                        outcome.map(move |outcome| (hash, inclusion, outcome))
+                    });
+                    future::join_all(found.collect::<Vec<_>>())
+                });
+                Either::B(events)
+            })
+    }
+
+    /// Searches the canonical blocks from `from` up to the best block for the
+    /// extrinsic `hash`.
+    pub fn scan(
+        &self,
+        hash: T::Hash,
+        from: T::BlockNumber,
+    ) -> impl Future<Item = Option<(Inclusion<T>, Option<Outcome>)>, Error = Error> {
+        self.scan_range(hash, from.saturated_into(), self.best_number().saturated_into())
+    }
+
+    /// Searches the canonical blocks from `from` up to and including `to` for
+    /// the extrinsic `hash`.
+    fn scan_range(
+        &self,
+        hash: T::Hash,
+        from: u64,
+        to: u64,
+    ) -> impl Future<Item = Option<(Inclusion<T>, Option<Outcome>)>, Error = Error> {
+        let tracker = self.clone();
+        stream::iter_ok(from..=to)
+            .and_then(move |number| {
+                let tracker = tracker.clone();
+                let number: T::BlockNumber = number.saturated_into();
+                tracker
+                    .client
+                    .block_hash(Some(number.into()))
+                    .map_err(Error::from)
+                    .and_then(move |block_hash| {
+                        match block_hash {
+                            Some(block_hash) => {
+                                let found =
+                                    tracker.inspect_block(block_hash, number, move |xt| {
+                                        *xt == hash
+                                    });
+                                Either::A(found)
+                            }
+                            None => Either::B(future::ok(Vec::new())),
+                        }
+                    })
+            })
+            .filter_map(|found| found.into_iter().next())
+            .into_future()
+            .map(|(found, _)| found.map(|(_, inclusion, outcome)| (inclusion, outcome)))
+            .map_err(|(err, _)| err)
+    }
+
+    /// Follows the chain and updates the state of tracked extrinsics.
+    ///
+    /// Subscribes again after `RETRY_DELAY` if a subscription fails.
+    pub fn run(self) -> impl Future<Item = (), Error = ()> {
+        future::loop_fn(self, |tracker| {
+            tracker.follow().then(move |result| {
+                match result {
+                    Ok(()) => log::warn!("tracker subscriptions ended, resubscribing"),
+                    Err(err) => log::error!("tracker failed, resubscribing: {:?}", err),
+                }
+                Delay::new(Instant::now() + RETRY_DELAY)
+                    .then(move |_| Ok::<_, ()>(Loop::Continue(tracker)))
+            })
+        })
+    }
+
+    fn follow(&self) -> impl Future<Item = (), Error = Error> {
+        let best = self.client.subscribe_blocks();
+        let finalized = self.client.subscribe_finalized_blocks();
+        let tracker = self.clone();
+        best.join(finalized)
+            .map_err(Error::from)
+            .and_then(move |(best, finalized)| {
+                best.map(Head::Best)
+                    .select(finalized.map(Head::Finalized))
+                    .map_err(Error::from)
+                    .for_each(move |head| {
+                        match head {
+                            Head::Best(header) => Either::A(tracker.process_best(header)),
+                            Head::Finalized(header) => {
+                                Either::B(tracker.process_finalized(header))
+                            }
+                        }
+                    })
+            })
+    }
+
+    /// Returns the blocks between the last inspected ancestor of `header` and
+    /// `header`, oldest first.
+    ///
+    /// Only returns `header` if no block was inspected yet.
+    fn uninspected(
+        &self,
+        header: T::Header,
+    ) -> impl Future<Item = Vec<(T::Hash, T::BlockNumber)>, Error = Error> {
+        let client = self.client.clone();
+        let inspected = self.inspected.clone();
+        future::loop_fn((header, Vec::new()), move |(header, mut blocks)| {
+            let number = *header.number();
+            let hash = header.hash();
+            let done = {
+                let inspected = inspected.lock().unwrap();
+                match inspected.keys().next() {
+                    Some(oldest) => number < *oldest || inspected.get(&number) == Some(&hash),
+                    None => !blocks.is_empty(),
+                }
+            };
+            if done {
+                blocks.reverse();
+                return Either::A(future::ok(Loop::Break(blocks)))
+            }
+            blocks.push((hash, number));
+            if number.is_zero() {
+                blocks.reverse();
+                return Either::A(future::ok(Loop::Break(blocks)))
+            }
+            let parent = client
+                .header(Some(*header.parent_hash()))
+                .map_err(Error::from)
+                .and_then(|parent| parent.ok_or(Error::UnknownBlock))
+                .map(move |parent| Loop::Continue((parent, blocks)));
+            Either::B(parent)
+        })
+    }
+
+    /// Updates the tracked extrinsics included in the best block `block_hash`.
+    fn inspect(
+        &self,
+        block_hash: T::Hash,
+        block_number: T::BlockNumber,
+    ) -> impl Future<Item = (), Error = Error> {
+        let tracker = self.clone();
+        let filter = {
+            let tracker = self.clone();
+            move |hash: &T::Hash| tracker.is_pending(hash)
+        };
+        self.inspect_block(block_hash, block_number, filter)
+            .map(move |found| {
+                for (hash, inclusion, outcome) in found {
+                    tracker.included(&hash, inclusion, outcome);
+                }
+                tracker.inspected.lock().unwrap().insert(block_number, block_hash);
+            })
+    }
+
+    fn process_best(&self, header: T::Header) -> impl Future<Item = (), Error = Error> {
+        let tracker = self.clone();
+        let block_hash = header.hash();
+        self.set_best_number(*header.number());
+        self.uninspected(header)
+            .and_then(move |blocks| {
+                let inspect = {
+                    let tracker = tracker.clone();
+                    stream::iter_ok(blocks).for_each(move |(hash, number)| {
+                        tracker.inspect(hash, number)
+                    })
+                };
+                inspect.and_then(move |()| tracker.check_dropped(block_hash))
+            })
+    }
+
+    /// Reports extrinsics waiting in the pool as dropped if the nonce of their
+    /// signer in the inspected block `block_hash` moved past their nonce.
+    fn check_dropped(self, block_hash: T::Hash) -> impl Future<Item = (), Error = Error> {
+        let mut signers: Vec<T::AccountId> = self
+            .txs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|tracked| tracked.status.state.is_in_pool())
+            .map(|tracked| tracked.signer.clone())
+            .collect();
+        signers.sort();
+        signers.dedup();
+        stream::iter_ok(signers).for_each(move |signer| {
+            let tracker = self.clone();
+            storage::account_nonce(&self.client, &signer, block_hash)
+                .map(move |nonce| tracker.dropped_before(&signer, nonce))
+        })
+    }
+
+    fn process_finalized(&self, header: T::Header) -> impl Future<Item = (), Error = Error> {
+        let number = *header.number();
+        let block_hash = header.hash();
+        {
+            // Blocks walking back from a new best block stop at the last
+            // inspected block not after the finalized block.
+            let mut inspected = self.inspected.lock().unwrap();
+            let keep = inspected.range(..=number).next_back().map(|(number, _)| *number);
+            if let Some(keep) = keep {
+                *inspected = inspected.split_off(&keep);
+            }
+        }
+        let tracker = self.clone();
+        self.check_expired(block_hash, number)
+            .and_then(move |()| tracker.finalize_included(number))
+    }
+
+    /// Resolves the extrinsics waiting in the pool whose era ended at the
+    /// finalized block `block_hash`.
+    ///
+    /// An extrinsic expired if the nonce of its signer in the finalized block
+    /// didn't move past its nonce. Otherwise the blocks of its era are
+    /// searched for it, and it is reported as dropped if none includes it.
+    fn check_expired(
+        &self,
+        block_hash: T::Hash,
+        number: T::BlockNumber,
+    ) -> impl Future<Item = (), Error = Error> {
+        let number: u64 = number.saturated_into();
+        let ended: Vec<_> = self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, tracked)| tracked.status.state.is_in_pool())
+            .filter_map(|(hash, tracked)| {
+                let death = tracked.status.death.filter(|death| *death <= number)?;
+                Some((*hash, tracked.signer.clone(), tracked.nonce, tracked.from, death))
+            })
+            .collect();
+        let tracker = self.clone();
+        stream::iter_ok(ended).for_each(move |(hash, signer, nonce, from, death)| {
+            let tracker = tracker.clone();
+            storage::account_nonce(&tracker.client, &signer, block_hash).and_then(
+                move |account_nonce| {
+                    if account_nonce <= nonce {
+                        tracker.expired(&hash);
+                        return Either::A(future::ok(()))
+                    }
+                    let scan = tracker
+                        .scan_range(hash, from.saturated_into(), death - 1)
+                        .map(move |found| {
+                            match found {
+                                Some((inclusion, outcome)) => {
+                                    tracker.included(&hash, inclusion, outcome)
+                                }
+                                None => tracker.dropped(&hash),
+                            }
+                        });
+                    Either::B(scan)
+                },
+            )
+        })
+    }
+
+    /// Marks the extrinsics included in canonical blocks up to the finalized
+    /// block `number` as finalized, and the others as retracted.
+    fn finalize_included(&self, number: T::BlockNumber) -> impl Future<Item = (), Error = Error> {
+        let included: Vec<_> = self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, tracked)| tracked.status.state == TxState::InBlock)
+            .filter_map(|(hash, tracked)| {
+                tracked
+                    .inclusion
+                    .as_ref()
+                    .filter(|inclusion| inclusion.block_number <= number)
+                    .map(|inclusion| (*hash, inclusion.block_hash, inclusion.block_number))
+            })
+            .collect();
+        let tracker = self.clone();
+        stream::iter_ok(included).for_each(move |(hash, block_hash, block_number)| {
+            let tracker = tracker.clone();
+            tracker
+                .client
+                .block_hash(Some(block_number.into()))
+                .map(move |canonical| {
+                    if canonical == Some(block_hash) {
+                        tracker.finalized(&hash);
+                    } else {
+                        tracker.retracted(&hash);
+                    }
+                })
+                .map_err(Error::from)
+        })
+    }
+}
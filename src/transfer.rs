@@ -0,0 +1,302 @@
+//! Transfer options and results.
+use crate::{
+    amount::Amount,
+    db::{
+        Batch,
+        Db,
+        Tree,
+    },
+    tracker::{
+        Inclusion,
+        Outcome,
+        TransactionStatus,
+        TxState,
+        Wait,
+    },
+    Error,
+    Exchange,
+};
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sr_primitives::{
+    generic::Era,
+    traits::SaturatedConversion,
+};
+use substrate_primitives::blake2_256;
+
+/// Optional parameters of a transfer.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransferOptions {
+    /// Point in the life of the extrinsic to wait for before returning.
+    pub wait: Wait,
+    /// Client supplied idempotency key.
+    ///
+    /// A transfer repeating the key of an earlier transfer returns the result
+    /// of the earlier transfer instead of sending a new extrinsic. Repeating
+    /// the key with another sender, recipient, amount, tip or `keep_alive` is
+    /// an error.
+    pub request_id: Option<String>,
+    /// Tip paid to the block author, overriding the configured default.
+    pub tip: Option<String>,
+    /// Reject the transfer if it would leave the sender below the existential
+    /// deposit. Set to `false` to empty an account.
+    pub keep_alive: bool,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            wait: Wait::default(),
+            request_id: None,
+            tip: None,
+            keep_alive: true,
+        }
+    }
+}
+
+/// Validity window of a mortal transaction.
+#[derive(Clone, Debug, Encode, Decode, Serialize)]
+pub struct Mortality {
+    /// First block the transaction is valid in.
+    pub birth: u64,
+    /// First block the transaction is no longer valid in.
+    pub death: u64,
+}
+
+impl Mortality {
+    /// Returns the validity window of `era` anchored at the block `current`,
+    /// `None` if `era` is immortal.
+    pub fn new(era: Era, current: u64) -> Option<Self> {
+        match era {
+            Era::Immortal => None,
+            Era::Mortal(..) => {
+                Some(Self {
+                    birth: era.birth(current),
+                    death: era.death(current),
+                })
+            }
+        }
+    }
+}
+
+/// Estimated fee of a transfer.
+#[derive(Clone, Debug, Serialize)]
+pub struct FeeEstimate {
+    /// Fee every transaction pays.
+    pub base_fee: Amount,
+    /// Fee for the encoded length of the extrinsic.
+    pub length_fee: Amount,
+    /// Fee for the dispatch weight of the call.
+    pub weight_fee: Amount,
+    /// Tip paid to the block author.
+    pub tip: Amount,
+    /// Sum of all fees and the tip.
+    pub total: Amount,
+    /// Encoded length of the extrinsic in bytes.
+    pub length: u32,
+    /// Dispatch weight of the call.
+    pub weight: u32,
+}
+
+/// Era and tip an extrinsic is signed with.
+pub struct Signing<T: Exchange> {
+    /// Era the extrinsic is valid in.
+    pub era: Era,
+    /// Hash of the block the era starts at.
+    pub era_hash: T::Hash,
+    /// Tip paid to the block author.
+    pub tip: T::Balance,
+    /// Validity window of the era, `None` if it is immortal.
+    pub mortality: Option<Mortality>,
+    /// Block the era was chosen at.
+    pub current: T::BlockNumber,
+}
+
+impl<T: Exchange> Signing<T> {
+    /// Returns the first block the extrinsic may be included in.
+    pub fn earliest(&self) -> T::BlockNumber {
+        match &self.mortality {
+            Some(mortality) => mortality.birth.saturated_into(),
+            None => self.current,
+        }
+    }
+}
+
+impl<T: Exchange> Clone for Signing<T> {
+    fn clone(&self) -> Self {
+        Self {
+            era: self.era,
+            era_hash: self.era_hash,
+            tip: self.tip,
+            mortality: self.mortality.clone(),
+            current: self.current,
+        }
+    }
+}
+
+/// Block a transaction was included in.
+#[derive(Clone, Debug, Encode, Decode, Serialize)]
+pub struct BlockInfo {
+    /// Hash of the block.
+    pub hash: String,
+    /// Number of the block.
+    pub number: u64,
+    /// Index of the extrinsic in the block.
+    pub index: u32,
+    /// Whether the block is finalized.
+    pub finalized: bool,
+}
+
+impl<T: Exchange> From<Inclusion<T>> for BlockInfo {
+    fn from(inclusion: Inclusion<T>) -> Self {
+        Self {
+            hash: format!("{:?}", inclusion.block_hash),
+            number: inclusion.block_number.saturated_into(),
+            index: inclusion.index,
+            finalized: inclusion.finalized,
+        }
+    }
+}
+
+/// Result of a transfer.
+#[derive(Clone, Debug, Serialize)]
+pub struct TransferResult {
+    /// Hash of the extrinsic.
+    pub hash: String,
+    /// Nonce of the signing account used for the extrinsic.
+    pub nonce: u64,
+    /// Transferred amount.
+    pub amount: Amount,
+    /// Fee paid for the extrinsic, including the tip.
+    pub fee: Amount,
+    /// Tip paid to the block author.
+    pub tip: Amount,
+    /// State of the extrinsic.
+    pub state: TxState,
+    /// Validity window of the extrinsic, `None` if it is immortal.
+    pub era: Option<Mortality>,
+    /// Block the extrinsic was included in, if waited for.
+    pub block: Option<BlockInfo>,
+    /// Dispatch outcome, once included.
+    pub outcome: Option<Outcome>,
+}
+
+impl TransferResult {
+    /// Updates the result with the latest status of the extrinsic.
+    pub fn update(&mut self, status: TransactionStatus) {
+        self.state = status.state;
+        self.block = status.block;
+        self.outcome = status.outcome;
+    }
+}
+
+/// Hash of the parameters of a transfer.
+pub type Fingerprint = [u8; 32];
+
+/// Name of the request tree.
+const TREE: &str = "transfer_requests";
+
+/// Layout version of the requests.
+const VERSION: u8 = 1;
+
+#[derive(Encode, Decode)]
+enum Request {
+    /// The transfer is being prepared.
+    Pending(Fingerprint),
+    /// The extrinsics with the hashes were signed and are recorded in the
+    /// outbox, the original extrinsic followed by its replacements.
+    Signed(Fingerprint, Vec<String>),
+}
+
+/// Persistent store of transfer idempotency keys.
+#[derive(Clone)]
+pub struct Requests {
+    requests: Tree<Request>,
+}
+
+impl Requests {
+    /// Opens the request store in `db`.
+    ///
+    /// Requests interrupted before their extrinsic was signed are removed, as
+    /// nothing was sent for them.
+    pub fn new(db: &Db) -> Result<Self, Error> {
+        let requests = db.tree(TREE)?;
+        let mut batch = Batch::default();
+        match db.version(TREE)? {
+            Some(VERSION) => {
+                for entry in requests.iter() {
+                    if let (key, Request::Pending(_)) = entry? {
+                        batch.remove(&requests, key);
+                    }
+                }
+            }
+            None if requests.is_empty() => db.set_version(&mut batch, TREE, VERSION)?,
+            _ => return Err(Error::CorruptedDatabase),
+        }
+        db.apply(batch)?;
+        Ok(Self { requests })
+    }
+
+    /// Returns the fingerprint of the transfer `parameters`.
+    pub fn fingerprint<P: Encode>(parameters: &P) -> Fingerprint {
+        blake2_256(&parameters.encode())
+    }
+
+    /// Claims the request id `id` for the transfer with the parameters
+    /// `fingerprint`.
+    ///
+    /// Returns the extrinsic hashes of the earlier transfer if the id was used
+    /// before, and fails if the earlier transfer had other parameters.
+    pub fn begin(
+        &self,
+        id: &str,
+        fingerprint: Fingerprint,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let request = Request::Pending(fingerprint);
+        match self.requests.insert_new(id.as_bytes(), &request)? {
+            None => Ok(None),
+            Some(Request::Pending(earlier)) | Some(Request::Signed(earlier, _))
+                if earlier != fingerprint =>
+            {
+                Err(Error::RequestMismatch)
+            }
+            Some(Request::Pending(_)) => Err(Error::RequestPending),
+            Some(Request::Signed(_, hashes)) => Ok(Some(hashes)),
+        }
+    }
+
+    /// Releases the request id `id` of a failed transfer.
+    ///
+    /// A request whose extrinsic was signed is kept unless the transaction
+    /// pool `rejected` the extrinsic, as it may be in the pool otherwise.
+    pub fn release(&self, id: &str, rejected: bool) -> Result<(), Error> {
+        match self.requests.get(id.as_bytes())? {
+            Some(Request::Signed(..)) if !rejected => {}
+            _ => {
+                self.requests.remove(id.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the signed extrinsic `hash` of the request `id` in `batch`,
+    /// keeping the extrinsics it replaces.
+    pub fn signed(&self, batch: &mut Batch, id: &str, hash: &str) -> Result<(), Error> {
+        let (fingerprint, mut hashes) = match self.requests.get(id.as_bytes())? {
+            Some(Request::Pending(fingerprint)) => (fingerprint, Vec::new()),
+            Some(Request::Signed(fingerprint, hashes)) => (fingerprint, hashes),
+            None => return Err(Error::CorruptedDatabase),
+        };
+        hashes.push(hash.to_string());
+        let request = Request::Signed(fingerprint, hashes);
+        batch.insert(&self.requests, id.as_bytes(), &request);
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+//! Webhook notifications.
+//!
+//! Deposits and withdrawals are reported by POSTing a json notification to
+//! every configured http or https webhook url. Notifications are written to a
+//! persistent queue before they are delivered, so they survive restarts. The
+//! notification of a deposit is queued in the transaction recording the
+//! deposit. A failed
+//! delivery is retried with exponential backoff, and later notifications to
+//! the same url wait for it, so every url receives the notifications in
+//! order.
+//!
+//! The body is signed with HMAC-SHA256 using the configured secret. The hex
+//! encoded signature is sent in the `X-Exchange-Signature` header as
+//! `sha256=<signature>`. The notification id in the body can be used to
+//! ignore a notification delivered twice.
+use crate::{
+    db::{
+        Batch,
+        Db,
+        Tree,
+    },
+    indexer::Deposit,
+    tracker::TransactionStatus,
+    Error,
+};
+use futures::{
+    future::{
+        self,
+        Either,
+        Future,
+    },
+    stream::{
+        self,
+        Stream,
+    },
+};
+use hmac::{
+    Hmac,
+    Mac,
+};
+use hyper::{
+    client::HttpConnector,
+    header::CONTENT_TYPE,
+    Body,
+    Client,
+    Request,
+    Uri,
+};
+use hyper_rustls::HttpsConnector;
+use parity_scale_codec::{
+    Decode,
+    Encode,
+};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::timer::{
+    Interval,
+    Timeout,
+};
+
+/// Header carrying the signature of the body.
+pub const SIGNATURE_HEADER: &str = "X-Exchange-Signature";
+
+/// Time a webhook has to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between checks for due deliveries.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest delay between two attempts of a delivery in seconds.
+const MAX_BACKOFF: u64 = 3600;
+/// Number of threads resolving the host names of webhooks.
+const DNS_THREADS: usize = 4;
+
+/// Webhook configuration.
+#[derive(Clone, Debug, Default)]
+pub struct WebhookConfig {
+    /// Urls notifications are posted to.
+    pub urls: Vec<String>,
+    /// Key the notifications are signed with, must not be empty if there are
+    /// urls.
+    pub secret: Vec<u8>,
+}
+
+/// Event reported to the webhooks.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    /// A deposit to a watched address was included in a best block.
+    DepositDetected(Deposit),
+    /// The block including a deposit was finalized.
+    DepositFinalized(Deposit),
+    /// The block including a deposit was retracted.
+    DepositOrphaned(Deposit),
+    /// A withdrawal was included in a best block and dispatched successfully.
+    WithdrawalIncluded(TransactionStatus),
+    /// The block including a successfully dispatched withdrawal was finalized.
+    WithdrawalFinalized(TransactionStatus),
+    /// A withdrawal failed at dispatch in a best or finalized block, or won't
+    /// be included.
+    WithdrawalFailed(TransactionStatus),
+}
+
+/// Body of a webhook request.
+#[derive(Serialize)]
+struct Notification<'a> {
+    /// Unique id of the notification.
+    id: u64,
+    /// Unix time the notification was created at in seconds.
+    created_at: u64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// A queued webhook request.
+#[derive(Encode, Decode)]
+struct Delivery {
+    /// Url the notification is posted to.
+    url: String,
+    /// Json encoded notification.
+    body: String,
+    /// Number of failed attempts.
+    attempts: u32,
+    /// Unix time of the next attempt in seconds.
+    next_attempt: u64,
+}
+
+/// Returns the current unix time in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// Returns the delay in seconds before retrying a delivery which failed
+/// `attempts` times.
+fn backoff(attempts: u32) -> u64 {
+    1u64.checked_shl(attempts).unwrap_or(MAX_BACKOFF).min(MAX_BACKOFF)
+}
+
+/// Returns the hex encoded HMAC-SHA256 of `body` keyed with `secret`.
+pub fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("hmac accepts keys of any length");
+    mac.input(body);
+    hex::encode(mac.result().code())
+}
+
+/// Persistent queue of webhook notifications.
+#[derive(Clone)]
+pub struct Webhooks {
+    client: Client<HttpsConnector<HttpConnector>>,
+    db: Db,
+    queue: Tree<Delivery>,
+    urls: Arc<Vec<String>>,
+    secret: Arc<Vec<u8>>,
+}
+
+impl Webhooks {
+    /// Opens the notification queue in `db`.
+    ///
+    /// Queued notifications to urls which are no longer configured are
+    /// removed. Fails if there are urls but no secret.
+    pub fn new(db: &Db, config: WebhookConfig) -> Result<Self, Error> {
+        for url in &config.urls {
+            let uri: Uri = url.parse().map_err(|_| Error::InvalidWebhookUrl)?;
+            match uri.scheme_str() {
+                Some("http") | Some("https") => {}
+                _ => return Err(Error::InvalidWebhookUrl),
+            }
+        }
+        if !config.urls.is_empty() && config.secret.is_empty() {
+            return Err(Error::EmptyWebhookSecret)
+        }
+        let queue = db.tree("webhook_queue")?;
+        for entry in queue.iter() {
+            let (key, delivery) = entry?;
+            if !config.urls.contains(&delivery.url) {
+                log::warn!("dropping notification to removed webhook {}", delivery.url);
+                queue.remove(key)?;
+            }
+        }
+        Ok(Self {
+            client: Client::builder().build(HttpsConnector::new(DNS_THREADS)),
+            db: db.clone(),
+            queue,
+            urls: Arc::new(config.urls),
+            secret: Arc::new(config.secret),
+        })
+    }
+
+    /// Queues a notification of `event` to every webhook.
+    pub fn notify(&self, event: Event) -> Result<(), Error> {
+        let mut batch = Batch::default();
+        self.enqueue(&mut batch, event)?;
+        self.db.apply(batch)
+    }
+
+    /// Queues a notification of `event` to every webhook in `batch`.
+    pub fn enqueue(&self, batch: &mut Batch, event: Event) -> Result<(), Error> {
+        if self.urls.is_empty() {
+            return Ok(())
+        }
+        let notification = Notification {
+            id: self.db.generate_id()?,
+            created_at: now(),
+            event: &event,
+        };
+        let body = serde_json::to_string(&notification).expect("notifications serialize");
+        for url in self.urls.iter() {
+            let delivery = Delivery {
+                url: url.clone(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: 0,
+            };
+            batch.insert(&self.queue, self.db.generate_id()?.to_be_bytes(), &delivery);
+        }
+        Ok(())
+    }
+
+    /// Posts `delivery`, resolving to `true` if the webhook accepted it.
+    fn post(&self, delivery: &Delivery) -> impl Future<Item = bool, Error = ()> {
+        let request = Request::post(delivery.url.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", signature(&self.secret, delivery.body.as_bytes())),
+            )
+            .body(Body::from(delivery.body.clone()));
+        let request = match request {
+            Ok(request) => request,
+            Err(err) => {
+                log::error!("invalid webhook request to {}: {:?}", delivery.url, err);
+                return Either::A(future::ok(false))
+            }
+        };
+        let url = delivery.url.clone();
+        let response = Timeout::new(self.client.request(request), TIMEOUT).then(move |response| {
+            match response {
+                Ok(response) if response.status().is_success() => Ok(true),
+                Ok(response) => {
+                    log::warn!("webhook {} responded with {}", url, response.status());
+                    Ok(false)
+                }
+                Err(err) => {
+                    log::warn!("webhook {} failed: {:?}", url, err);
+                    Ok(false)
+                }
+            }
+        });
+        Either::B(response)
+    }
+
+    /// Attempts the delivery `key`, rescheduling it if it fails.
+    fn deliver(
+        &self,
+        key: Vec<u8>,
+        mut delivery: Delivery,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let webhooks = self.clone();
+        self.post(&delivery).then(move |delivered| {
+            if delivered == Ok(true) {
+                webhooks.queue.remove(key)?;
+            } else {
+                delivery.attempts += 1;
+                delivery.next_attempt = now() + backoff(delivery.attempts);
+                webhooks.queue.insert(key, &delivery)?;
+            }
+            webhooks.queue.flush()?;
+            Ok(delivered == Ok(true))
+        })
+    }
+
+    /// Attempts the due deliveries in queue order.
+    ///
+    /// A url is skipped once one of its deliveries is not due or fails.
+    fn process(&self) -> impl Future<Item = (), Error = Error> {
+        let entries = match self.queue.iter().collect::<Result<Vec<_>, _>>() {
+            Ok(entries) => entries,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        let webhooks = self.clone();
+        let now = now();
+        let process = stream::iter_ok(entries)
+            .fold(HashSet::new(), move |mut blocked, (key, delivery)| {
+                if blocked.contains(&delivery.url) {
+                    return Either::A(future::ok(blocked))
+                }
+                if delivery.next_attempt > now {
+                    blocked.insert(delivery.url);
+                    return Either::A(future::ok(blocked))
+                }
+                let url = delivery.url.clone();
+                let deliver = webhooks.deliver(key, delivery).map(move |delivered| {
+                    if !delivered {
+                        blocked.insert(url);
+                    }
+                    blocked
+                });
+                Either::B(deliver)
+            })
+            .map(|_| ());
+        Either::B(process)
+    }
+
+    /// Delivers the queued notifications.
+    pub fn run(self) -> impl Future<Item = (), Error = ()> {
+        Interval::new_interval(POLL_INTERVAL)
+            .map_err(|err| log::error!("webhook timer failed: {:?}", err))
+            .for_each(move |_| {
+                self.process().or_else(|err| {
+                    log::error!("failed to deliver webhooks: {:?}", err);
+                    Ok(())
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231 test case 2.
+        assert_eq!(
+            signature(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), 2);
+        assert_eq!(backoff(5), 32);
+        assert_eq!(backoff(12), MAX_BACKOFF);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+}